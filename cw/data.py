@@ -90,6 +90,12 @@ def plot_mean_times(results, output_path='decode_times_comparison.png'):
 file_paths = [
     './test_yar/json_results.csv',
     './decoder/bit_packed_18_bit_results.csv',
+    './decoder/gaps_results.csv',
+    './decoder/bytewise_results.csv',
+    './decoder/vec_results.csv',
+    './decoder/hash_set_results.csv',
+    './decoder/sorted_vec_results.csv',
+    './decoder/bitset_results.csv',
     './test_u32/u32_results.csv'
 ]
 
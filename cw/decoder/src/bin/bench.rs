@@ -0,0 +1,260 @@
+// times encoding and decoding a full 512x512 board in every payload format and board container,
+// appending the results to one csv per variant for data.py to compare
+
+use csv::{Writer, WriterBuilder};
+use decoder::board::{BitBoard, Board, SortedCells};
+use decoder::{coord_bits, gaps, packing, FunctionCall, Header, Packet, PayloadType};
+use indexmap::IndexSet;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::time::Instant;
+
+const BYTE: usize = 8;
+
+fn test(run: i32, writers: &mut ResultWriters) {
+    let image: u32 = 512;
+    let mut world: Vec<u8> = Vec::with_capacity((image * image) as usize);
+    let mut buffer: u32 = 0;
+    let coordinate_length = coord_bits(image) * 2;
+    println!("{:032b}", coordinate_length);
+    let mut bit_count: usize = coordinate_length as usize - 1;
+    let mask: u32 = generate_mask(coordinate_length);
+    let indiv_len = coord_bits(image);
+    for x in 0..image {
+        for y in 0..image {
+            let new_num: u32 = (x << indiv_len) | y;
+            buffer |= new_num << (31 - bit_count);
+            bit_count += coordinate_length as usize;
+            while bit_count >= 32 {
+                let byte = buffer & mask;
+                bit_count -= BYTE;
+                buffer <<= BYTE;
+
+                world.push((byte >> 24) as u8);
+            }
+        }
+    }
+
+    let mut pending = bit_count + 1 - coordinate_length as usize;
+    while pending > 0 {
+        world.push((buffer >> 24) as u8);
+        buffer <<= BYTE;
+        pending = pending.saturating_sub(BYTE);
+    }
+
+    println!("size {}", world.len());
+
+    let header = Header {
+        version: 0,
+        fn_call: FunctionCall::ProcessSlice,
+        payload_type: PayloadType::Sparse,
+        msg_id: 0,
+        width: image as u16,
+        height: image as u16,
+        turn: 0,
+        length: world.len() as u32,
+        cell_count: image * image,
+        fragment: 0,
+        fragment_count: 1,
+        checksum: 0,
+    };
+
+    let packet = Packet {
+        header,
+        cells: IndexSet::new(),
+    };
+    println!("{:?}", packet);
+
+    let cells_len = (image * image) as usize;
+    let now = Instant::now();
+    let cells: IndexSet<u32> = packet.decode_payload(&world, coordinate_length, cells_len);
+    let elapsed_decode = now.elapsed();
+
+    println!("cells decoded in {:.2?} seconds", elapsed_decode);
+
+    let gap_cells = cells.clone();
+
+    let now = Instant::now();
+    let _ = packet.encode_payload(&cells, coordinate_length as usize);
+    let elapsed_encode = now.elapsed();
+    println!("encoded cells processed in {:.2?} seconds", elapsed_encode);
+
+    let now = Instant::now();
+    packet.decode_payload::<IndexSet<u32>>(&world, coordinate_length, cells_len);
+    let elapsed_decode_again = now.elapsed();
+
+    writers
+        .packed
+        .write_record([
+            "Decode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_decode),
+        ])
+        .unwrap();
+    writers
+        .packed
+        .write_record([
+            "Encode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_encode),
+        ])
+        .unwrap();
+    writers
+        .packed
+        .write_record([
+            "Decode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_decode_again),
+        ])
+        .unwrap();
+    writers.packed.flush().unwrap();
+
+    // same board through the varint gap encoding, written alongside for comparison
+    let now = Instant::now();
+    let gap_payload = gaps::encode(&gap_cells);
+    let elapsed_gaps_encode = now.elapsed();
+
+    let now = Instant::now();
+    gaps::decode(&gap_payload).unwrap();
+    let elapsed_gaps_decode = now.elapsed();
+    println!(
+        "gaps: {} bytes against {} bit packed, encoded in {:.2?} decoded in {:.2?}",
+        gap_payload.len(),
+        world.len(),
+        elapsed_gaps_encode,
+        elapsed_gaps_decode
+    );
+
+    writers
+        .gaps
+        .write_record([
+            "Decode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_gaps_decode),
+        ])
+        .unwrap();
+    writers
+        .gaps
+        .write_record([
+            "Encode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_gaps_encode),
+        ])
+        .unwrap();
+    writers.gaps.flush().unwrap();
+
+    // the byte at a time unpacking the word loads replaced, and the word loads into a plain vec
+    let now = Instant::now();
+    let mut bytewise_cells = IndexSet::with_capacity(cells_len);
+    packing::Unpacker::<u64>::new(coordinate_length, cells_len as u32).feed(&world, |cell| {
+        bytewise_cells.insert(cell as u32);
+    });
+    let elapsed_bytewise_decode = now.elapsed();
+
+    let now = Instant::now();
+    packet.decode_payload_vec(&world, coordinate_length, cells_len);
+    let elapsed_vec_decode = now.elapsed();
+    println!(
+        "byte at a time decoded in {:.2?}, into a vec in {:.2?}",
+        elapsed_bytewise_decode, elapsed_vec_decode
+    );
+
+    writers
+        .bytewise
+        .write_record([
+            "Decode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_bytewise_decode),
+        ])
+        .unwrap();
+    writers.bytewise.flush().unwrap();
+    writers
+        .vec
+        .write_record([
+            "Decode",
+            &format!("{:?}", run),
+            &format!("{:.2?}", elapsed_vec_decode),
+        ])
+        .unwrap();
+    writers.vec.flush().unwrap();
+
+    // the same payload into each of the other board containers
+    test_board::<HashSet<u32>>(run, &packet, &world, &mut writers.hash_set);
+    test_board::<SortedCells>(run, &packet, &world, &mut writers.sorted_vec);
+    test_board::<BitBoard>(run, &packet, &world, &mut writers.bitset);
+}
+
+/// times decoding the benchmark payload into a `B` and encoding it back out
+fn test_board<B: Board>(run: i32, packet: &Packet, world: &[u8], wtr: &mut Writer<File>) {
+    let coordinate_length = packet.coord_len();
+    let cell_count = packet.header.cell_count as usize;
+
+    let now = Instant::now();
+    let cells: B = packet.decode_payload(world, coordinate_length, cell_count);
+    let elapsed_decode = now.elapsed();
+
+    let now = Instant::now();
+    let _ = packet.encode_payload(&cells, coordinate_length as usize);
+    let elapsed_encode = now.elapsed();
+
+    wtr.write_record([
+        "Decode",
+        &format!("{:?}", run),
+        &format!("{:.2?}", elapsed_decode),
+    ])
+    .unwrap();
+    wtr.write_record([
+        "Encode",
+        &format!("{:?}", run),
+        &format!("{:.2?}", elapsed_encode),
+    ])
+    .unwrap();
+    wtr.flush().unwrap();
+}
+
+/// one csv per encoding or board container the benchmark compares
+struct ResultWriters {
+    packed: Writer<File>,
+    gaps: Writer<File>,
+    bytewise: Writer<File>,
+    vec: Writer<File>,
+    hash_set: Writer<File>,
+    sorted_vec: Writer<File>,
+    bitset: Writer<File>,
+}
+
+fn main() {
+    let mut writers = ResultWriters {
+        packed: results_writer("results.csv"),
+        gaps: results_writer("gaps_results.csv"),
+        bytewise: results_writer("bytewise_results.csv"),
+        vec: results_writer("vec_results.csv"),
+        hash_set: results_writer("hash_set_results.csv"),
+        sorted_vec: results_writer("sorted_vec_results.csv"),
+        bitset: results_writer("bitset_results.csv"),
+    };
+    for i in 0..2000 {
+        test(i, &mut writers)
+    }
+}
+
+fn results_writer(path: &str) -> Writer<File> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .unwrap();
+    let mut wtr = WriterBuilder::new().has_headers(false).from_writer(file);
+    wtr.write_record(["Operation", "Run", "Time (seconds)"])
+        .unwrap();
+    wtr
+}
+
+/// generates mask of left aligned 1's where there are `coordinate_length` number of 1's
+fn generate_mask(coordinate_length: u32) -> u32 {
+    if coordinate_length > 32 {
+        panic!("coordinate length must be less than or equal to 32");
+    }
+    let mask = !0u32;
+    mask << (32 - coordinate_length)
+}
@@ -0,0 +1,71 @@
+use indexmap::IndexSet;
+
+use crate::{coord_bits, DecodeError, BYTE};
+
+// the dense payload: one bit per cell of the board, 1 for alive, most significant bit first.
+// cells are numbered column by column, `x * height + y`, the same order as the packed coordinates,
+// and the final byte is padded with zeros.
+
+/// size in bytes of the bitmap for a `width` x `height` board
+pub fn encoded_len(width: u32, height: u32) -> usize {
+    (width as usize * height as usize).div_ceil(BYTE)
+}
+
+pub fn encode(cells: &IndexSet<u32>, width: u32, height: u32) -> Result<Vec<u8>, DecodeError> {
+    let y_bits = coord_bits(height);
+    let mut data = vec![0u8; encoded_len(width, height)];
+    for &cell in cells {
+        let x = cell >> y_bits;
+        let y = cell & ((1 << y_bits) - 1);
+        let index = x as usize * height as usize + y as usize;
+        if x >= width || y >= height {
+            // where the cell would have landed in the bitmap
+            return Err(DecodeError::CoordinateOutOfRange {
+                x,
+                y,
+                width,
+                height,
+                offset: index / BYTE,
+            });
+        }
+        data[index / BYTE] |= 0x80 >> (index % BYTE);
+    }
+    Ok(data)
+}
+
+pub fn decode(data: &[u8], width: u32, height: u32) -> IndexSet<u32> {
+    let mut cells = IndexSet::new();
+    decode_chunk(data, 0, width, height, |cell| {
+        cells.insert(cell);
+    });
+    cells
+}
+
+/// decodes `chunk`, the bitmap bytes from byte `offset` on, passing each live cell to `emit`.
+/// every byte stands alone, so a bitmap can be decoded in pieces as it arrives
+pub fn decode_chunk<F: FnMut(u32)>(
+    chunk: &[u8],
+    offset: usize,
+    width: u32,
+    height: u32,
+    mut emit: F,
+) {
+    let y_bits = coord_bits(height);
+    let cell_count = width as usize * height as usize;
+    for (i, &byte) in chunk.iter().enumerate() {
+        let mut byte = byte;
+        while byte != 0 {
+            // take the highest set bit left in the byte
+            let bit = byte.leading_zeros() as usize;
+            byte &= !(0x80 >> bit);
+
+            let index = (offset + i) * BYTE + bit;
+            if index >= cell_count {
+                return;
+            }
+            let x = (index / height as usize) as u32;
+            let y = (index % height as usize) as u32;
+            emit(x << y_bits | y);
+        }
+    }
+}
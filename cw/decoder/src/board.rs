@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+
+use indexmap::IndexSet;
+
+use crate::coord_bits;
+
+// containers a decoded board can be held in. which one suits depends on the Life algorithm:
+// `IndexSet` keeps payload order, `HashSet` drops that for cheaper inserts, `SortedCells` is
+// compact and ordered for merging, and `BitBoard` is one bit per coordinate with O(1) lookups
+// for boards that are mostly alive.
+
+/// a set of packed `x << y_bits | y` cells
+pub trait Board: Sized {
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// an empty container for a `width` x `height` board that will hold about `capacity` cells,
+    /// for containers sized by the board rather than the number of cells
+    fn for_board(_width: u32, _height: u32, capacity: usize) -> Self {
+        Self::with_capacity(capacity)
+    }
+
+    /// adds `cell`, returning false if it was already there
+    fn insert(&mut self, cell: u32) -> bool;
+
+    fn contains(&self, cell: u32) -> bool;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// every cell, in whatever order the container keeps them
+    fn cells(&self) -> impl Iterator<Item = u32> + '_;
+
+    /// the `index`th cell in iteration order
+    fn nth(&self, index: usize) -> Option<u32> {
+        self.cells().nth(index)
+    }
+}
+
+impl Board for IndexSet<u32> {
+    fn with_capacity(capacity: usize) -> Self {
+        IndexSet::with_capacity(capacity)
+    }
+
+    fn insert(&mut self, cell: u32) -> bool {
+        IndexSet::insert(self, cell)
+    }
+
+    fn contains(&self, cell: u32) -> bool {
+        IndexSet::contains(self, &cell)
+    }
+
+    fn len(&self) -> usize {
+        IndexSet::len(self)
+    }
+
+    fn cells(&self) -> impl Iterator<Item = u32> + '_ {
+        self.iter().copied()
+    }
+
+    fn nth(&self, index: usize) -> Option<u32> {
+        self.get_index(index).copied()
+    }
+}
+
+impl Board for HashSet<u32> {
+    fn with_capacity(capacity: usize) -> Self {
+        HashSet::with_capacity(capacity)
+    }
+
+    fn insert(&mut self, cell: u32) -> bool {
+        HashSet::insert(self, cell)
+    }
+
+    fn contains(&self, cell: u32) -> bool {
+        HashSet::contains(self, &cell)
+    }
+
+    fn len(&self) -> usize {
+        HashSet::len(self)
+    }
+
+    fn cells(&self) -> impl Iterator<Item = u32> + '_ {
+        self.iter().copied()
+    }
+}
+
+/// cells in ascending order without duplicates, looked up by binary search
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SortedCells(Vec<u32>);
+
+impl SortedCells {
+    pub fn as_slice(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl Board for SortedCells {
+    fn with_capacity(capacity: usize) -> Self {
+        SortedCells(Vec::with_capacity(capacity))
+    }
+
+    fn insert(&mut self, cell: u32) -> bool {
+        // payloads are usually written in ascending order, so this is mostly a push
+        match self.0.last() {
+            Some(&last) if last < cell => {
+                self.0.push(cell);
+                true
+            }
+            None => {
+                self.0.push(cell);
+                true
+            }
+            _ => match self.0.binary_search(&cell) {
+                Ok(_) => false,
+                Err(index) => {
+                    self.0.insert(index, cell);
+                    true
+                }
+            },
+        }
+    }
+
+    fn contains(&self, cell: u32) -> bool {
+        self.0.binary_search(&cell).is_ok()
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn cells(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().copied()
+    }
+
+    fn nth(&self, index: usize) -> Option<u32> {
+        self.0.get(index).copied()
+    }
+}
+
+/// one bit per packed coordinate, grown to fit the highest cell inserted.
+/// made with `for_board` it never grows past the board, and cells beyond it aren't inserted,
+/// so a bad coordinate in a payload can't make it allocate far more than the board needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitBoard {
+    words: Vec<u64>,
+    len: usize,
+    // one past the highest packed coordinate that can be inserted
+    limit: u64,
+}
+
+impl Default for BitBoard {
+    fn default() -> Self {
+        BitBoard {
+            words: Vec::new(),
+            len: 0,
+            limit: 1 << u32::BITS,
+        }
+    }
+}
+
+impl Board for BitBoard {
+    fn with_capacity(_capacity: usize) -> Self {
+        // the size depends on the highest cell, not how many there are
+        BitBoard::default()
+    }
+
+    fn for_board(width: u32, height: u32, _capacity: usize) -> Self {
+        let y_bits = coord_bits(height);
+        BitBoard {
+            // the top corner, x = width - 1 and y = height - 1, is the highest cell
+            limit: ((width.saturating_sub(1) as u64) << y_bits) + height as u64,
+            ..BitBoard::default()
+        }
+    }
+
+    /// adds `cell`, returning false if it was already there or lies beyond the board
+    fn insert(&mut self, cell: u32) -> bool {
+        if cell as u64 >= self.limit {
+            return false;
+        }
+        let (word, bit) = (cell as usize / 64, cell % 64);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let was_set = self.words[word] & (1 << bit) != 0;
+        self.words[word] |= 1 << bit;
+        if !was_set {
+            self.len += 1;
+        }
+        !was_set
+    }
+
+    fn contains(&self, cell: u32) -> bool {
+        self.words
+            .get(cell as usize / 64)
+            .is_some_and(|word| word & (1 << (cell % 64)) != 0)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn cells(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros();
+                word &= word - 1;
+                Some(i as u32 * 64 + bit)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(x: u32, y: u32, height: u32) -> u32 {
+        x << coord_bits(height) | y
+    }
+
+    /// the same inserts and lookups on any container
+    fn check_board<B: Board>() {
+        let (width, height) = (100, 37);
+        let mut board = B::for_board(width, height, 4);
+        let cells = [
+            pack(5, 7, height),
+            0,
+            pack(width - 1, height - 1, height),
+            pack(1, 0, height),
+        ];
+        for cell in cells {
+            assert!(board.insert(cell));
+        }
+        assert!(!board.insert(0));
+        assert_eq!(board.len(), cells.len());
+        assert!(cells.iter().all(|&cell| board.contains(cell)));
+        assert!(!board.contains(pack(5, 8, height)));
+
+        let mut listed: Vec<u32> = board.cells().collect();
+        listed.sort_unstable();
+        let mut expected = cells.to_vec();
+        expected.sort_unstable();
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn every_board_holds_the_same_cells() {
+        check_board::<IndexSet<u32>>();
+        check_board::<HashSet<u32>>();
+        check_board::<SortedCells>();
+        check_board::<BitBoard>();
+    }
+
+    #[test]
+    fn bit_board_stays_within_the_board() {
+        let (width, height) = (100, 37);
+        let mut board = BitBoard::for_board(width, height, 0);
+        assert!(board.insert(pack(width - 1, height - 1, height)));
+        let words = board.words.len();
+
+        // a coordinate off the board is turned away rather than growing the bitmap to fit it
+        assert!(!board.insert(u32::MAX));
+        assert!(!board.insert(pack(width, 0, height)));
+        assert_eq!(board.words.len(), words);
+        assert_eq!(board.len(), 1);
+    }
+}
@@ -0,0 +1,426 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::future::try_join_all;
+use futures::{SinkExt, StreamExt};
+use indexmap::IndexSet;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+use tokio_util::codec::Framed;
+
+use crate::client::Client;
+use crate::codec::PacketCodec;
+use crate::delta::{self, Generations};
+use crate::handshake::accept_handshake;
+use crate::heartbeat::Heartbeat;
+use crate::{coord_bits, DecodeError, FunctionCall, Packet, PayloadType};
+
+// the broker sits between the controller that owns the board and the pool of workers.
+// a controller sends the whole board as a `ProcessSlice` packet, the broker cuts it into bands of
+// rows, hands each band to the least loaded worker, and replies with the stitched next generation.
+//
+// cells are packed as `x << y_bits | y` and rows are indexed by y. every band is sent with the
+// row above and below it (wrapping round the board) so workers can count neighbours at the edges.
+// workers can't know which rows are halo, so the broker drops anything they return outside the band.
+//
+// every worker connection is kept under heartbeat. a worker that stops answering is taken out of
+// the pool, and any band it was working on is handed to another worker.
+
+/// a connected worker and the number of slices it is currently working on
+struct Worker {
+    addr: SocketAddr,
+    client: Arc<Client<TcpStream>>,
+    load: Arc<AtomicUsize>,
+}
+
+/// one slice counted against a worker's load, released when dropped so a slice abandoned
+/// mid-request (say because a sibling slice failed the turn) doesn't keep counting
+struct Load(Arc<AtomicUsize>);
+
+impl Drop for Load {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct WorkerPool {
+    workers: Mutex<Vec<Worker>>,
+}
+
+impl WorkerPool {
+    async fn add(&self, worker: Worker) {
+        self.workers.lock().await.push(worker);
+    }
+
+    async fn remove(&self, addr: SocketAddr) {
+        self.workers
+            .lock()
+            .await
+            .retain(|worker| worker.addr != addr);
+    }
+
+    async fn len(&self) -> usize {
+        self.workers.lock().await.len()
+    }
+
+    /// picks the worker with the lowest load and counts the slice against it straight away,
+    /// so slices handed out in the same turn spread over the pool
+    async fn least_loaded(&self) -> Option<(SocketAddr, Arc<Client<TcpStream>>, Load)> {
+        let workers = self.workers.lock().await;
+        let worker = workers
+            .iter()
+            .filter(|worker| !worker.client.is_closed())
+            .min_by_key(|worker| worker.load.load(Ordering::Relaxed))?;
+        worker.load.fetch_add(1, Ordering::Relaxed);
+        Some((
+            worker.addr,
+            worker.client.clone(),
+            Load(worker.load.clone()),
+        ))
+    }
+}
+
+/// runs the broker: workers connect on `worker_addr`, controllers send boards to `controller_addr`.
+/// workers are pinged as `heartbeat` says and dropped once they stop answering
+pub async fn run<A, B>(
+    worker_addr: A,
+    controller_addr: B,
+    heartbeat: Heartbeat,
+) -> Result<(), DecodeError>
+where
+    A: ToSocketAddrs,
+    B: ToSocketAddrs,
+{
+    let pool = Arc::new(WorkerPool::default());
+    let workers = TcpListener::bind(worker_addr).await?;
+    let controllers = TcpListener::bind(controller_addr).await?;
+    println!(
+        "broker accepting workers on {} and controllers on {}",
+        workers.local_addr()?,
+        controllers.local_addr()?
+    );
+
+    tokio::spawn(accept_workers(workers, pool.clone(), heartbeat));
+    loop {
+        let (stream, addr) = controllers.accept().await?;
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_controller(stream, pool).await {
+                eprintln!("controller {} disconnected: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn accept_workers(listener: TcpListener, pool: Arc<WorkerPool>, heartbeat: Heartbeat) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("failed to accept worker; err = {:?}", e);
+                continue;
+            }
+        };
+        let mut framed = Framed::new(stream, PacketCodec::new());
+        if let Err(e) = accept_handshake(&mut framed).await {
+            eprintln!("worker {} failed the handshake: {}", addr, e);
+            continue;
+        }
+        println!("worker {} connected", addr);
+        // a big slice can take a while, whether the worker is still there is up to the heartbeat
+        let client = Arc::new(Client::new(framed).without_timeout());
+        client.spawn_heartbeat(heartbeat);
+        tokio::spawn(remove_when_closed(pool.clone(), addr, client.clone()));
+        pool.add(Worker {
+            addr,
+            client,
+            load: Arc::new(AtomicUsize::new(0)),
+        })
+        .await;
+    }
+}
+
+/// takes a worker out of the pool once its connection closes or it misses a heartbeat
+async fn remove_when_closed(
+    pool: Arc<WorkerPool>,
+    addr: SocketAddr,
+    client: Arc<Client<TcpStream>>,
+) {
+    client.closed().await;
+    pool.remove(addr).await;
+    println!("worker {} disconnected", addr);
+}
+
+async fn serve_controller(stream: TcpStream, pool: Arc<WorkerPool>) -> Result<(), DecodeError> {
+    let mut framed = Framed::new(stream, PacketCodec::new());
+    accept_handshake(&mut framed).await?;
+
+    // boards from the controller may be deltas against the previous board it sent,
+    // and replies are deltas against the board they answer
+    let mut received = Generations::default();
+    let mut last_reply: Option<Packet> = None;
+
+    while let Some(packet) = framed.next().await {
+        let mut packet = packet?;
+        match packet.header.fn_call {
+            FunctionCall::ProcessSlice => {
+                match received.resolve(&mut packet) {
+                    Ok(()) => {}
+                    Err(DecodeError::MissingKeyframe(turn)) => {
+                        eprintln!("controller sent a delta against unknown turn {}", turn);
+                        packet.header.fn_call = FunctionCall::Keyframe;
+                        packet.header.payload_type = PayloadType::Sparse;
+                        packet.cells.clear();
+                        framed.send(packet).await?;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+                let next = match next_generation(&packet, &pool).await {
+                    Ok(next) => next,
+                    Err(e) => {
+                        // one failed turn shouldn't cost the controller its session
+                        eprintln!("turn {} failed: {}", packet.header.turn, e);
+                        packet.header.fn_call = FunctionCall::Failed;
+                        packet.cells.clear();
+                        framed.send(packet).await?;
+                        continue;
+                    }
+                };
+                let board = std::mem::replace(&mut packet.cells, next);
+                packet.header.turn = packet.header.turn.wrapping_add(1);
+                last_reply = Some(packet.clone());
+                delta::delta_against(&mut packet, &board);
+                framed.send(packet).await?;
+            }
+            FunctionCall::Keyframe => {
+                // the controller couldn't apply the last reply, send it again in full
+                let mut reply = last_reply.clone().unwrap_or_default();
+                reply.header.fn_call = FunctionCall::Keyframe;
+                reply.header.msg_id = packet.header.msg_id;
+                framed.send(reply).await?;
+            }
+            FunctionCall::Quit => break,
+            fn_call => eprintln!("broker ignoring {:?} from controller", fn_call),
+        }
+    }
+    Ok(())
+}
+
+/// splits the board into one band of rows per worker, farms the bands out and stitches the
+/// results back into the next generation of the whole board
+async fn next_generation(board: &Packet, pool: &WorkerPool) -> Result<IndexSet<u32>, DecodeError> {
+    let height = board.header.height as u32;
+    let y_bits = coord_bits(height);
+
+    let slices = (pool.len().await as u32).min(height);
+    if slices == 0 {
+        return Err(DecodeError::Other("no workers connected".to_string()));
+    }
+
+    let requests = (0..slices).map(|i| {
+        let start = i * height / slices;
+        let end = (i + 1) * height / slices;
+        let halo_above = (start + height - 1) % height;
+        let halo_below = end % height;
+        let mut slice = Packet::new();
+        slice.header.fn_call = FunctionCall::ProcessSlice;
+        slice.header.width = board.header.width;
+        slice.header.height = board.header.height;
+        slice.header.turn = board.header.turn;
+        slice.cells = board
+            .cells
+            .iter()
+            .copied()
+            .filter(|&cell| {
+                let row = row(cell, y_bits);
+                (start..end).contains(&row) || row == halo_above || row == halo_below
+            })
+            .collect();
+        process_slice(pool, slice, start, end, y_bits)
+    });
+
+    let mut next = IndexSet::with_capacity(board.cells.len());
+    for cells in try_join_all(requests).await? {
+        next.extend(cells);
+    }
+    Ok(next)
+}
+
+/// sends one band to the least loaded worker and keeps only the rows that band owns.
+/// if the worker dies before answering, the band goes to the next least loaded one
+async fn process_slice(
+    pool: &WorkerPool,
+    slice: Packet,
+    start: u32,
+    end: u32,
+    y_bits: u32,
+) -> Result<Vec<u32>, DecodeError> {
+    // slices always go out whole, workers answer with a delta against them
+    let mut sent = Generations::default();
+    sent.record(slice.header.turn, slice.cells.clone());
+    let mut response = loop {
+        let (addr, client, load) = pool
+            .least_loaded()
+            .await
+            .ok_or_else(|| DecodeError::Other("no workers connected".to_string()))?;
+        let response = client.send_request(slice.clone()).await;
+        drop(load);
+
+        match response {
+            Ok(response) if response.header.fn_call == FunctionCall::ProcessSlice => {
+                break response
+            }
+            // anything else (a Failed or Keyframe reply) carries no cells for this band
+            Ok(response) => {
+                eprintln!(
+                    "worker {} answered a slice with {:?}",
+                    addr, response.header.fn_call
+                );
+                return Err(DecodeError::Other(format!(
+                    "worker answered a slice with {:?}",
+                    response.header.fn_call
+                )));
+            }
+            Err(e) if client.is_closed() => {
+                eprintln!(
+                    "worker {} died with a slice in flight, reassigning: {}",
+                    addr, e
+                );
+                pool.remove(addr).await;
+            }
+            Err(e) => {
+                eprintln!("worker {} failed to process a slice: {}", addr, e);
+                return Err(e);
+            }
+        }
+    };
+    sent.resolve(&mut response)?;
+    Ok(response
+        .cells
+        .into_iter()
+        .filter(|cell| (start..end).contains(&row(*cell, y_bits)))
+        .collect())
+}
+
+fn row(cell: u32, y_bits: u32) -> u32 {
+    cell & ((1 << y_bits) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a worker that answers every request with `fn_call`, or never answers if that's `None`
+    async fn fake_worker(fn_call: Option<FunctionCall>) -> Worker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, PacketCodec::new());
+            while let Some(Ok(mut packet)) = framed.next().await {
+                if let Some(fn_call) = fn_call {
+                    packet.header.fn_call = fn_call;
+                    packet.cells.clear();
+                    framed.send(packet).await.unwrap();
+                }
+            }
+        });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        Worker {
+            addr,
+            client: Arc::new(
+                Client::new(Framed::new(stream, PacketCodec::new())).without_timeout(),
+            ),
+            load: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn blinker() -> Packet {
+        let mut board = Packet::new();
+        board.header.fn_call = FunctionCall::ProcessSlice;
+        board.header.width = 8;
+        board.header.height = 8;
+        let y_bits = coord_bits(8);
+        board.cells.extend((2..5).map(|y| 3 << y_bits | y));
+        board
+    }
+
+    #[tokio::test]
+    async fn reply_other_than_process_slice_fails_the_slice() {
+        for fn_call in [FunctionCall::Failed, FunctionCall::Keyframe] {
+            let pool = WorkerPool::default();
+            pool.add(fake_worker(Some(fn_call)).await).await;
+
+            let result = next_generation(&blinker(), &pool).await;
+            assert!(
+                matches!(result, Err(DecodeError::Other(_))),
+                "{:?} reply gave {:?}",
+                fn_call,
+                result
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_turn_releases_every_slice_load() {
+        let pool = WorkerPool::default();
+        let failing = fake_worker(Some(FunctionCall::Failed)).await;
+        let silent = fake_worker(None).await;
+        let loads = [failing.load.clone(), silent.load.clone()];
+        pool.add(failing).await;
+        pool.add(silent).await;
+
+        // one band fails straight away, the other is dropped while its worker is still on it
+        assert!(next_generation(&blinker(), &pool).await.is_err());
+        for load in loads {
+            assert_eq!(load.load(Ordering::Relaxed), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn delta_against_an_unknown_turn_asks_for_a_keyframe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_controller(stream, Arc::new(WorkerPool::default())).await
+        });
+        let controller = Client::connect(addr).await.unwrap();
+
+        let mut delta = blinker();
+        delta.header.turn = 5;
+        delta.header.payload_type = PayloadType::Delta;
+        let reply = controller.send_request(delta).await.unwrap();
+        assert_eq!(reply.header.fn_call, FunctionCall::Keyframe);
+        // an empty keyframe request, not an empty delta the controller would try to apply
+        assert_eq!(reply.header.payload_type, PayloadType::Sparse);
+        assert!(reply.cells.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_turn_keeps_the_controller_connected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // no workers ever join this pool
+            serve_controller(stream, Arc::new(WorkerPool::default())).await
+        });
+        let controller = Client::connect(addr).await.unwrap();
+
+        let mut board = Packet::new();
+        board.header.width = 8;
+        board.header.height = 8;
+        board.header.turn = 3;
+        board.cells.insert(9);
+        for _ in 0..2 {
+            let reply = controller.send_request(board.clone()).await.unwrap();
+            assert_eq!(reply.header.fn_call, FunctionCall::Failed);
+            assert_eq!(reply.header.turn, 3);
+            assert!(reply.cells.is_empty());
+        }
+    }
+}
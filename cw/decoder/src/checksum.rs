@@ -0,0 +1,60 @@
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff, no reflection, no final xor
+
+const POLYNOMIAL: u16 = 0x1021;
+pub const INITIAL: u16 = 0xffff;
+
+const TABLE: [u16; 256] = build_table();
+
+const fn build_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// feeds `data` into a running checksum, one table lookup per byte
+pub fn update(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        crc = (crc << 8) ^ TABLE[((crc >> 8) as u8 ^ byte) as usize];
+    }
+    crc
+}
+
+/// checksum of a whole frame: every header byte before the checksum field, then the payload
+pub fn frame_checksum(header: &[u8], payload: &[u8]) -> u16 {
+    update(update(INITIAL, header), payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_answer() {
+        // the check value every CRC-16/CCITT-FALSE implementation agrees on
+        assert_eq!(update(INITIAL, b"123456789"), 0x29B1);
+        assert_eq!(update(INITIAL, b""), INITIAL);
+    }
+
+    #[test]
+    fn checksum_runs_on_over_any_split() {
+        let data = b"123456789";
+        for split in 0..=data.len() {
+            let (header, payload) = data.split_at(split);
+            assert_eq!(frame_checksum(header, payload), 0x29B1);
+        }
+    }
+}
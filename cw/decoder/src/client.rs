@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio_util::codec::Framed;
+
+use crate::codec::PacketCodec;
+use crate::handshake::handshake;
+use crate::heartbeat::{heartbeat_packet, Heartbeat};
+use crate::{DecodeError, Packet};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type PendingRequests = Arc<Mutex<HashMap<u16, oneshot::Sender<Packet>>>>;
+
+/// sends requests over a single connection and matches each response to its request by `msg_id`,
+/// so any number of requests can be in flight at once.
+///
+/// a background task reads every incoming packet and hands it to whoever is waiting on its id.
+/// when the connection drops the pending map is emptied, which fails every outstanding request.
+/// the same happens when the peer stops answering heartbeats, see `spawn_heartbeat`.
+pub struct Client<T> {
+    sink: Mutex<SplitSink<Framed<T, PacketCodec>, Packet>>,
+    pending: PendingRequests,
+    closed: Arc<AtomicBool>,
+    // flips to true once, when the connection is given up on
+    dead: Arc<watch::Sender<bool>>,
+    msg_id_counter: AtomicU16,
+    timeout: Option<Duration>,
+    reader: JoinHandle<()>,
+}
+
+impl Client<TcpStream> {
+    /// connects to `addr` and negotiates the protocol version before handing back the client
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, DecodeError> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut framed = Framed::new(stream, PacketCodec::new());
+        handshake(&mut framed).await?;
+        Ok(Self::new(framed))
+    }
+}
+
+impl<T> Client<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    pub fn new(framed: Framed<T, PacketCodec>) -> Self {
+        let (sink, stream) = framed.split();
+        let pending: PendingRequests = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let dead = Arc::new(watch::Sender::new(false));
+        let reader = tokio::spawn(read_responses(
+            stream,
+            pending.clone(),
+            closed.clone(),
+            dead.clone(),
+        ));
+        Self {
+            sink: Mutex::new(sink),
+            pending,
+            closed,
+            dead,
+            msg_id_counter: AtomicU16::new(0),
+            timeout: Some(DEFAULT_TIMEOUT),
+            reader,
+        }
+    }
+
+    /// how long `send_request` waits to send a request and get its response before giving up on it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// lets `send_request` wait as long as the response takes, for requests that can run long
+    /// on a peer whose liveness is checked some other way, such as by `spawn_heartbeat`
+    pub fn without_timeout(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+
+    /// sends `packet` under a fresh message id and waits for the response carrying the same id
+    pub async fn send_request(&self, packet: Packet) -> Result<Packet, DecodeError> {
+        self.request(packet, self.timeout).await
+    }
+
+    /// true once the connection has closed or the peer has been declared dead
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// waits until the connection closes or the peer is declared dead. by then every request
+    /// still in flight on it has failed, so whatever they carried can be sent elsewhere
+    pub async fn closed(&self) {
+        let mut dead = self.dead.subscribe();
+        // the sender lives in `self`, so this can't fail while we're borrowing it
+        let _ = dead.wait_for(|dead| *dead).await;
+    }
+
+    async fn request(
+        &self,
+        mut packet: Packet,
+        timeout: Option<Duration>,
+    ) -> Result<Packet, DecodeError> {
+        let (tx, rx) = oneshot::channel();
+        let msg_id = {
+            let mut pending = self.pending.lock().await;
+            if self.closed.load(Ordering::Acquire) {
+                return Err(DecodeError::Other("connection closed".to_string()));
+            }
+            if pending.len() > u16::MAX as usize {
+                return Err(DecodeError::Other(
+                    "every message id is already in flight".to_string(),
+                ));
+            }
+            // the counter wraps, so skip over ids still waiting on an old response
+            let mut msg_id = self.next_msg_id();
+            while pending.contains_key(&msg_id) {
+                msg_id = self.next_msg_id();
+            }
+            pending.insert(msg_id, tx);
+            msg_id
+        };
+        packet.header.msg_id = msg_id;
+
+        // the timeout covers getting the packet out as well, as a peer that stops reading would
+        // otherwise leave the send, and the sink lock, stuck. giving up part way is safe: the
+        // codec encodes whole frames into the write buffer, so the rest goes with the next send.
+        // closing the client gives up on it too, so a send stuck behind a dead peer lets go of the
+        // sink lock as soon as the heartbeat notices
+        let closed = || {
+            DecodeError::Other(format!(
+                "connection closed before response to message {}",
+                msg_id
+            ))
+        };
+        let exchange = async {
+            tokio::select! {
+                biased;
+                response = async {
+                    if let Err(e) = self.sink.lock().await.send(packet).await {
+                        // a failed write leaves the connection unusable for every request
+                        if matches!(e, DecodeError::Io(_)) {
+                            close(&self.pending, &self.closed, &self.dead).await;
+                        }
+                        return Err(e);
+                    }
+                    rx.await.map_err(|_| closed())
+                } => response,
+                () = self.closed() => Err(closed()),
+            }
+        };
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(DecodeError::Other(format!(
+                        "no response to message {} after {:?}",
+                        msg_id, timeout
+                    )))
+                }),
+            None => exchange.await,
+        };
+        if result.is_err() {
+            self.pending.lock().await.remove(&msg_id);
+        }
+        result
+    }
+
+    /// next id from the 16-bit counter, wrapping back to 0 after `u16::MAX`
+    fn next_msg_id(&self) -> u16 {
+        self.msg_id_counter.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl<T> Client<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// pings the peer every `heartbeat.interval` and declares it dead the first time a ping
+    /// goes unanswered for `heartbeat.timeout`, closing the client as if the connection had dropped.
+    /// the task only holds a weak reference, so it stops once the client is dropped or closed
+    pub fn spawn_heartbeat(self: &Arc<Self>, heartbeat: Heartbeat) -> JoinHandle<()> {
+        let client = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(heartbeat.interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                if client.is_closed() {
+                    break;
+                }
+                if let Err(e) = client
+                    .request(heartbeat_packet(), Some(heartbeat.timeout))
+                    .await
+                {
+                    eprintln!("peer missed a heartbeat, closing the connection: {}", e);
+                    close(&client.pending, &client.closed, &client.dead).await;
+                    break;
+                }
+            }
+        })
+    }
+}
+
+impl<T> Drop for Client<T> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+async fn read_responses<T>(
+    mut stream: SplitStream<Framed<T, PacketCodec>>,
+    pending: PendingRequests,
+    closed: Arc<AtomicBool>,
+    dead: Arc<watch::Sender<bool>>,
+) where
+    T: AsyncRead + AsyncWrite,
+{
+    while let Some(result) = stream.next().await {
+        let packet = match result {
+            Ok(packet) => packet,
+            Err(e) => {
+                // the stream can't be resynchronised after a bad frame, so treat it as closed
+                eprintln!("dropping connection after decode error: {}", e);
+                break;
+            }
+        };
+        let tx = pending.lock().await.remove(&packet.header.msg_id);
+        match tx {
+            Some(tx) => {
+                // the requester may have timed out in the meantime, nothing to do then
+                let _ = tx.send(packet);
+            }
+            None => eprintln!(
+                "discarding response to unknown message {}",
+                packet.header.msg_id
+            ),
+        }
+    }
+
+    close(&pending, &closed, &dead).await;
+}
+
+/// stops new requests, fails every pending one and wakes anyone waiting on `Client::closed`
+async fn close(pending: &PendingRequests, closed: &AtomicBool, dead: &watch::Sender<bool>) {
+    // dropping the senders wakes every waiting request with an error
+    let mut pending = pending.lock().await;
+    closed.store(true, Ordering::Release);
+    pending.clear();
+    dead.send_replace(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FunctionCall;
+
+    /// a board big enough that it can't all sit in the pipe's buffer
+    fn big_packet() -> Packet {
+        let mut packet = Packet::new();
+        packet.header.width = 1024;
+        packet.header.height = 1024;
+        packet.cells = (0..20_000u32)
+            .map(|i| i.wrapping_mul(2654435761) >> 12)
+            .collect();
+        packet
+    }
+
+    #[tokio::test]
+    async fn request_gets_its_own_response() {
+        let (near, far) = tokio::io::duplex(64 * 1024);
+        let client = Client::new(Framed::new(near, PacketCodec::new()));
+        let mut peer = Framed::new(far, PacketCodec::new());
+        tokio::spawn(async move {
+            while let Some(Ok(mut packet)) = peer.next().await {
+                packet.header.fn_call = FunctionCall::AliveCellCount;
+                peer.send(packet).await.unwrap();
+            }
+        });
+
+        let response = client.send_request(big_packet()).await.unwrap();
+        assert_eq!(response.header.fn_call, FunctionCall::AliveCellCount);
+        assert_eq!(response.cells, big_packet().cells);
+    }
+
+    /// a peer that waits for `count` requests and then answers them last first
+    fn reversing_peer(far: tokio::io::DuplexStream, count: usize) {
+        let mut peer = Framed::new(far, PacketCodec::new());
+        tokio::spawn(async move {
+            let mut requests = Vec::new();
+            while requests.len() < count {
+                requests.push(peer.next().await.unwrap().unwrap());
+            }
+            for packet in requests.into_iter().rev() {
+                peer.send(packet).await.unwrap();
+            }
+        });
+    }
+
+    /// a request told apart from the others by its turn and its one cell
+    fn numbered(i: u32) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.width = 64;
+        packet.header.height = 64;
+        packet.header.turn = i;
+        packet.cells.insert(i);
+        packet
+    }
+
+    #[tokio::test]
+    async fn responses_out_of_order_reach_their_requests() {
+        let (near, far) = tokio::io::duplex(64 * 1024);
+        let client = Client::new(Framed::new(near, PacketCodec::new()));
+        reversing_peer(far, 5);
+
+        let requests = (0..5).map(|i| client.send_request(numbered(i)));
+        let responses = futures::future::join_all(requests).await;
+        for (i, response) in responses.into_iter().enumerate() {
+            let response = response.unwrap();
+            assert_eq!(response.header.turn, i as u32);
+            assert_eq!(response.cells, numbered(i as u32).cells);
+        }
+    }
+
+    #[tokio::test]
+    async fn wrapped_counter_skips_ids_still_in_flight() {
+        let (near, far) = tokio::io::duplex(64 * 1024);
+        let client = Arc::new(Client::new(Framed::new(near, PacketCodec::new())));
+        reversing_peer(far, 3);
+
+        // the last id before the wrap and the first after it, both left waiting
+        client.msg_id_counter.store(u16::MAX, Ordering::Relaxed);
+        let waiting: Vec<_> = (0..2)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move { client.send_request(numbered(i)).await })
+            })
+            .collect();
+        while client.pending.lock().await.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let mut in_flight: Vec<u16> = client.pending.lock().await.keys().copied().collect();
+        in_flight.sort_unstable();
+        assert_eq!(in_flight, [0, u16::MAX]);
+
+        // the counter comes round to them again
+        client.msg_id_counter.store(u16::MAX, Ordering::Relaxed);
+        let response = client.send_request(numbered(2)).await.unwrap();
+        assert_eq!(response.header.msg_id, 1);
+        assert_eq!(response.header.turn, 2);
+        for (i, waiting) in waiting.into_iter().enumerate() {
+            let response = waiting.await.unwrap().unwrap();
+            assert_eq!(response.header.turn, i as u32);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_to_a_peer_that_stops_reading_times_out() {
+        let (near, _far) = tokio::io::duplex(1024);
+        let client = Client::new(Framed::new(near, PacketCodec::new()))
+            .with_timeout(Duration::from_millis(100));
+
+        let request = client.send_request(big_packet());
+        let result = tokio::time::timeout(Duration::from_secs(2), request).await;
+        assert!(result.expect("send was never timed out").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_without_timeout_waits_for_a_slow_peer() {
+        let (near, far) = tokio::io::duplex(64 * 1024);
+        let client = Client::new(Framed::new(near, PacketCodec::new())).without_timeout();
+        let mut peer = Framed::new(far, PacketCodec::new());
+        tokio::spawn(async move {
+            let packet = peer.next().await.unwrap().unwrap();
+            tokio::time::sleep(DEFAULT_TIMEOUT * 4).await;
+            peer.send(packet).await.unwrap();
+        });
+
+        assert!(client.send_request(Packet::new()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn missed_heartbeat_fails_a_stuck_request() {
+        let (near, _far) = tokio::io::duplex(1024);
+        let client = Arc::new(Client::new(Framed::new(near, PacketCodec::new())).without_timeout());
+        client.spawn_heartbeat(Heartbeat {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(200),
+        });
+
+        let request = tokio::spawn({
+            let client = client.clone();
+            async move { client.send_request(big_packet()).await }
+        });
+        tokio::time::timeout(Duration::from_secs(2), client.closed())
+            .await
+            .expect("peer never declared dead");
+        let result = tokio::time::timeout(Duration::from_secs(2), request)
+            .await
+            .expect("stuck request was never woken");
+        assert!(result.unwrap().is_err());
+    }
+}
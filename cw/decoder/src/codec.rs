@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bytes::BytesMut;
+use indexmap::IndexSet;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::reassembly::Reassembly;
+use crate::{
+    checksum, DecodeError, FunctionCall, Header, Packet, CHECKSUM, HEADER_SIZE_BYTES,
+    MAX_MESSAGE_BYTES, MAX_PAYLOAD_BYTES, PROTOCOL_VERSION, REASSEMBLY_TIMEOUT,
+};
+
+/// frames a byte stream into `Packet`s so a `TcpStream` can be wrapped in a `Framed`.
+///
+/// the header is decoded as soon as it has fully arrived and kept until the rest of the
+/// payload turns up, so a frame may be split over any number of reads and a single read
+/// may carry several frames.
+///
+/// every frame except handshakes is written in, and must arrive in, the codec's protocol
+/// version. that starts as `PROTOCOL_VERSION` and is replaced by whatever the handshake settles on.
+///
+/// payloads over `MAX_PAYLOAD_BYTES` are sent as numbered fragments under the same `msg_id` and
+/// put back together here before decoding. a message that goes the reassembly timeout without
+/// another of its fragments arriving fails the next decode with `MissingFragments`. what a peer
+/// can make the codec buffer is bounded: a message over the size limit, or one more message
+/// starting while `MAX_REASSEMBLIES` are already part way through, fails with `PayloadTooLarge`.
+#[derive(Debug)]
+pub struct PacketCodec {
+    header: Option<Header>,
+    version: u8,
+    fragments: HashMap<u16, Pending>,
+    reassembly_timeout: Duration,
+    max_message_bytes: usize,
+    strict: bool,
+}
+
+/// most fragmented messages the codec will put back together at once
+pub const MAX_REASSEMBLIES: usize = 16;
+
+/// a message part way through arriving, and its payload so far
+#[derive(Debug)]
+struct Pending {
+    reassembly: Reassembly,
+    payload: BytesMut,
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketCodec {
+    pub fn new() -> Self {
+        Self {
+            header: None,
+            version: PROTOCOL_VERSION,
+            fragments: HashMap::new(),
+            reassembly_timeout: REASSEMBLY_TIMEOUT,
+            max_message_bytes: MAX_MESSAGE_BYTES,
+            strict: false,
+        }
+    }
+
+    /// how long a fragmented message may wait for each of its fragments after the first
+    pub fn with_reassembly_timeout(mut self, timeout: Duration) -> Self {
+        self.reassembly_timeout = timeout;
+        self
+    }
+
+    /// the largest payload a message may carry over all its fragments, `MAX_MESSAGE_BYTES` unless
+    /// set lower here. the encoder keeps to `MAX_MESSAGE_BYTES` whatever this is
+    pub fn with_max_message_bytes(mut self, max: usize) -> Self {
+        self.max_message_bytes = max;
+        self
+    }
+
+    /// whether to reject decoded cells that are off the board or repeated, rather than
+    /// keeping them as they come. see `Packet::decode_strict`
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+}
+
+impl Decoder for PacketCodec {
+    type Item = Packet;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, DecodeError> {
+        // checked on every call rather than only when a frame completes, so a message whose last
+        // fragments never come is reported as soon as any more bytes arrive
+        self.expire_fragments()?;
+        // keep taking frames until one completes a message or `src` runs dry
+        while let Some((header, payload)) = self.next_frame(src)? {
+            if let Some(packet) = self.reassemble(header, payload)? {
+                return Ok(Some(packet));
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, DecodeError> {
+        if let Some(packet) = self.decode(src)? {
+            return Ok(Some(packet));
+        }
+        // whatever is left is the start of a frame the stream ended in the middle of
+        if let Some(header) = self.header.take() {
+            return Err(DecodeError::LengthMismatch {
+                expected: header.length as usize,
+                received: src.len() - HEADER_SIZE_BYTES,
+            });
+        }
+        if !src.is_empty() {
+            return Err(DecodeError::TruncatedHeader {
+                received: src.len(),
+            });
+        }
+        // the stream ended on a message still waiting for fragments
+        let stalled = self.fragments.keys().next().copied();
+        match stalled.and_then(|msg_id| self.fragments.remove(&msg_id)) {
+            Some(pending) => pending.reassembly.stopped().map(|()| None),
+            None => Ok(None),
+        }
+    }
+}
+
+impl PacketCodec {
+    /// takes the next whole frame off `src` once it has all arrived, with its checksum verified
+    fn next_frame(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<(Header, BytesMut)>, DecodeError> {
+        let header = match self.header.take() {
+            Some(header) => header,
+            None => {
+                if src.len() < HEADER_SIZE_BYTES {
+                    src.reserve(HEADER_SIZE_BYTES - src.len());
+                    return Ok(None);
+                }
+                let mut packet = Packet::new();
+                packet.decode_header(&src[..HEADER_SIZE_BYTES])?;
+                packet.header.check_version(self.version)?;
+                packet.header.check_frame()?;
+                packet.header
+            }
+        };
+
+        // the header bytes stay in `src` until the whole frame is here, the checksum covers both
+        let frame_length = HEADER_SIZE_BYTES + header.length as usize;
+        if src.len() < frame_length {
+            // wait for the rest of the payload, reserving room for it up front
+            src.reserve(frame_length - src.len());
+            self.header = Some(header);
+            return Ok(None);
+        }
+
+        let mut payload = src.split_to(frame_length);
+        let header_bytes = payload.split_to(HEADER_SIZE_BYTES);
+        let packet = Packet {
+            header,
+            cells: IndexSet::new(),
+        };
+        packet.verify_checksum(&header_bytes, &payload)?;
+        Ok(Some((packet.header, payload)))
+    }
+
+    /// adds a frame to the message it belongs to, decoding the message once it is complete
+    fn reassemble(
+        &mut self,
+        header: Header,
+        payload: BytesMut,
+    ) -> Result<Option<Packet>, DecodeError> {
+        self.expire_fragments()?;
+        let mut pending = match self.fragments.remove(&header.msg_id) {
+            Some(pending) => pending,
+            // a message in one frame is decoded straight away and never waits here
+            None if !header.is_last_fragment() && self.fragments.len() >= MAX_REASSEMBLIES => {
+                // no room left for another message part way through
+                return Err(DecodeError::PayloadTooLarge {
+                    length: header.length as usize,
+                    max: 0,
+                });
+            }
+            None => Pending {
+                reassembly: Reassembly::new(self.reassembly_timeout, self.max_message_bytes),
+                payload: BytesMut::new(),
+            },
+        };
+        pending.reassembly.check(&header)?;
+        pending.payload.unsplit(payload);
+
+        let msg_id = header.msg_id;
+        let Some(header) = pending.reassembly.add(header) else {
+            self.fragments.insert(msg_id, pending);
+            return Ok(None);
+        };
+        let mut packet = Packet {
+            header,
+            cells: IndexSet::new(),
+        };
+        packet.cells = packet.decode_cells(&pending.payload, self.strict)?;
+        Ok(Some(packet))
+    }
+
+    /// fails on the first message that has been waiting on fragments for too long
+    fn expire_fragments(&mut self) -> Result<(), DecodeError> {
+        let expired = self
+            .fragments
+            .iter()
+            .find(|(_, pending)| pending.reassembly.is_expired())
+            .map(|(msg_id, _)| *msg_id);
+        match expired.and_then(|msg_id| self.fragments.remove(&msg_id)) {
+            Some(pending) => pending.reassembly.stopped(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Encoder<Packet> for PacketCodec {
+    type Error = DecodeError;
+
+    fn encode(&mut self, mut packet: Packet, dst: &mut BytesMut) -> Result<(), DecodeError> {
+        if packet.header.fn_call != FunctionCall::Handshake {
+            packet.header.version = self.version;
+        }
+        let cells = std::mem::take(&mut packet.cells);
+        let payload = packet.encode_cells(cells)?;
+
+        // an empty payload still goes out as one frame
+        if payload.len() > MAX_MESSAGE_BYTES {
+            return Err(DecodeError::PayloadTooLarge {
+                length: payload.len(),
+                max: MAX_MESSAGE_BYTES,
+            });
+        }
+        let fragment_count = payload.len().div_ceil(MAX_PAYLOAD_BYTES).max(1);
+        packet.header.fragment_count = fragment_count as u16;
+
+        dst.reserve(fragment_count * HEADER_SIZE_BYTES + payload.len());
+        for fragment in 0..fragment_count {
+            let start = fragment * MAX_PAYLOAD_BYTES;
+            let chunk = &payload[start..payload.len().min(start + MAX_PAYLOAD_BYTES)];
+            packet.header.fragment = fragment as u16;
+            packet.header.length = chunk.len() as u32;
+
+            let header_bytes = packet.header.to_bytes();
+            packet.header.checksum = checksum::frame_checksum(&header_bytes[..CHECKSUM], chunk);
+
+            packet.header.encode(dst);
+            dst.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{encode, first_fragment, fragmented_board, frames};
+    use crate::{coord_bits, PayloadType};
+
+    /// small deterministic generator so failures reproduce
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 32) as u32
+        }
+
+        /// a board side, mostly small but up to the full 16 bits
+        fn side(&mut self) -> u32 {
+            let max = match self.next() % 3 {
+                0 => 4,
+                1 => 100,
+                _ => u16::MAX as u32,
+            };
+            1 + self.next() % max
+        }
+    }
+
+    fn board(rng: &mut Lcg) -> Packet {
+        let (width, height) = (rng.side(), rng.side());
+        let y_bits = coord_bits(height);
+        let mut packet = Packet::new();
+        packet.header.width = width as u16;
+        packet.header.height = height as u16;
+        for _ in 0..rng.next() % 100 {
+            packet
+                .cells
+                .insert((rng.next() % width) << y_bits | (rng.next() % height));
+        }
+        // cell 0 packs to all zero bits, the same as the padding after it
+        if rng.next() & 1 == 0 {
+            packet.cells.insert(0);
+        }
+        packet
+    }
+
+    /// encodes `packet` and decodes it again, feeding the bytes in random sized reads
+    fn round_trip(rng: &mut Lcg, packet: Packet) -> Packet {
+        let mut codec = PacketCodec::new();
+        let mut encoded = BytesMut::new();
+        codec.encode(packet, &mut encoded).unwrap();
+
+        let mut src = BytesMut::new();
+        let mut decoded = None;
+        let mut rest = &encoded[..];
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at((rng.next() as usize % 4096 + 1).min(rest.len()));
+            src.extend_from_slice(chunk);
+            rest = tail;
+            if let Some(packet) = codec.decode(&mut src).unwrap() {
+                assert!(decoded.is_none(), "one packet decoded twice");
+                decoded = Some(packet);
+            }
+        }
+        assert!(src.is_empty());
+        decoded.expect("packet never decoded")
+    }
+
+    #[test]
+    fn random_boards_round_trip() {
+        let mut rng = Lcg(3);
+        for _ in 0..2000 {
+            let board = board(&mut rng);
+            for payload_type in [PayloadType::Sparse, PayloadType::Delta, PayloadType::Gaps] {
+                let mut packet = board.clone();
+                packet.header.payload_type = payload_type;
+                let decoded = round_trip(&mut rng, packet);
+                assert_eq!(
+                    decoded.cells, board.cells,
+                    "{}x{} {:?}",
+                    board.header.width, board.header.height, payload_type
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fragmented_message_round_trips() {
+        let mut rng = Lcg(4);
+        let mut packet = fragmented_board(100_000);
+        packet.cells.insert(0);
+
+        let decoded = round_trip(&mut rng, packet.clone());
+        assert!(packet.delta_len(packet.cells.len()) > MAX_PAYLOAD_BYTES);
+        assert_eq!(decoded.cells, packet.cells);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_message_fails_the_next_decode() {
+        let mut codec = PacketCodec::new();
+        let mut src = first_fragment();
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        tokio::time::advance(REASSEMBLY_TIMEOUT).await;
+
+        // not even a whole header, but enough to have the codec look again
+        src.extend_from_slice(&[PROTOCOL_VERSION]);
+        match codec.decode(&mut src) {
+            Err(DecodeError::MissingFragments {
+                msg_id: 7,
+                received: 1,
+                ..
+            }) => {}
+            other => panic!("expected missing fragments, got {:?}", other),
+        }
+        assert!(codec.fragments.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_fragments_reassemble_while_they_keep_coming() {
+        let board = fragmented_board(40_000);
+        let frames = frames(encode(board.clone()));
+        assert_eq!(frames.len(), 4);
+
+        // each fragment well within the timeout of the last, the whole message well over it
+        let mut codec = PacketCodec::new();
+        let mut decoded = None;
+        for mut frame in frames {
+            tokio::time::advance(REASSEMBLY_TIMEOUT - Duration::from_secs(1)).await;
+            decoded = codec.decode(&mut frame).unwrap();
+        }
+        assert_eq!(decoded.expect("message never completed").cells, board.cells);
+    }
+
+    #[test]
+    fn message_over_the_size_limit_is_rejected() {
+        let mut codec = PacketCodec::new().with_max_message_bytes(MAX_PAYLOAD_BYTES);
+        let mut frames = frames(encode(fragmented_board(20_000)));
+        let total = frames.iter().map(BytesMut::len).sum::<usize>() - 2 * HEADER_SIZE_BYTES;
+        assert!(codec.decode(&mut frames[0]).unwrap().is_none());
+        match codec.decode(&mut frames[1]) {
+            Err(DecodeError::PayloadTooLarge {
+                length,
+                max: MAX_PAYLOAD_BYTES,
+            }) if length == total => {}
+            other => panic!("expected the message to be too large, got {:?}", other),
+        }
+        assert!(codec.fragments.is_empty());
+    }
+
+    #[test]
+    fn too_many_messages_at_once_are_rejected() {
+        let first_fragment = |msg_id| {
+            let mut board = fragmented_board(20_000);
+            board.header.msg_id = msg_id;
+            frames(encode(board)).swap_remove(0)
+        };
+        let mut codec = PacketCodec::new();
+        for msg_id in 0..MAX_REASSEMBLIES as u16 {
+            assert!(codec.decode(&mut first_fragment(msg_id)).unwrap().is_none());
+        }
+        assert!(matches!(
+            codec.decode(&mut first_fragment(100)),
+            Err(DecodeError::PayloadTooLarge { .. })
+        ));
+        assert_eq!(codec.fragments.len(), MAX_REASSEMBLIES);
+
+        // a message in one frame never has to wait, so it still gets through
+        let mut packet = Packet::new();
+        packet.header.msg_id = 200;
+        packet.header.width = 8;
+        packet.header.height = 8;
+        packet.cells.insert(9);
+        let decoded = codec.decode(&mut encode(packet.clone())).unwrap();
+        assert_eq!(decoded.unwrap().cells, packet.cells);
+    }
+
+    #[test]
+    fn stream_ending_mid_message_is_missing_fragments() {
+        let mut codec = PacketCodec::new();
+        let mut src = first_fragment();
+        assert!(matches!(
+            codec.decode_eof(&mut src),
+            Err(DecodeError::MissingFragments { received: 1, .. })
+        ));
+        assert!(codec.fragments.is_empty());
+    }
+
+    #[test]
+    fn stream_ending_mid_header_is_truncated_header() {
+        let mut src = first_fragment();
+        src.truncate(HEADER_SIZE_BYTES - 1);
+        let error = PacketCodec::new().decode_eof(&mut src).unwrap_err();
+        assert!(matches!(
+            error,
+            DecodeError::TruncatedHeader {
+                received
+            } if received == HEADER_SIZE_BYTES - 1
+        ));
+        assert!(std::error::Error::source(&error).is_none());
+    }
+
+    #[test]
+    fn stream_ending_mid_payload_is_length_mismatch() {
+        let mut src = first_fragment();
+        src.truncate(HEADER_SIZE_BYTES + 100);
+        let error = PacketCodec::new().decode_eof(&mut src).unwrap_err();
+        assert!(matches!(
+            error,
+            DecodeError::LengthMismatch {
+                expected: MAX_PAYLOAD_BYTES,
+                received: 100,
+            }
+        ));
+        assert!(std::error::Error::source(&error).is_none());
+    }
+
+    #[test]
+    fn strict_codec_rejects_off_board_cells() {
+        let (width, height) = (100, 37);
+        for payload_type in [PayloadType::Sparse, PayloadType::Gaps] {
+            let mut packet = Packet::new();
+            packet.header.width = width;
+            packet.header.height = height;
+            packet.header.payload_type = payload_type;
+            let y_bits = coord_bits(height as u32);
+            packet.cells.extend([1 << y_bits | 2, 100 << y_bits | 5]);
+            let mut encoded = BytesMut::new();
+            PacketCodec::new()
+                .encode(packet.clone(), &mut encoded)
+                .unwrap();
+
+            let lenient = PacketCodec::new().decode(&mut encoded.clone()).unwrap();
+            assert_eq!(lenient.unwrap().cells, packet.cells);
+            match PacketCodec::new().with_strict(true).decode(&mut encoded) {
+                Err(DecodeError::CoordinateOutOfRange { x: 100, y: 5, .. }) => {}
+                other => panic!("{:?} gave {:?}", payload_type, other),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_byte_fails_the_checksum() {
+        let mut packet = Packet::new();
+        packet.header.width = 100;
+        packet.header.height = 37;
+        packet.cells.extend([1, 300, 2000]);
+        let encoded = encode(packet);
+
+        // the turn in the header, then the first and last payload bytes
+        for at in [crate::TURN, HEADER_SIZE_BYTES, encoded.len() - 1] {
+            let mut corrupted = encoded.clone();
+            corrupted[at] ^= 0x10;
+            assert!(
+                matches!(
+                    PacketCodec::new().decode(&mut corrupted),
+                    Err(DecodeError::BadChecksum { .. })
+                ),
+                "byte {}",
+                at
+            );
+        }
+    }
+
+    #[test]
+    fn empty_board_is_one_bare_header() {
+        let mut packet = Packet::new();
+        packet.header.width = 8;
+        packet.header.height = 8;
+        let mut encoded = BytesMut::new();
+        PacketCodec::new().encode(packet, &mut encoded).unwrap();
+        assert_eq!(encoded.len(), HEADER_SIZE_BYTES);
+
+        let decoded = PacketCodec::new().decode(&mut encoded).unwrap().unwrap();
+        assert!(decoded.cells.is_empty());
+    }
+}
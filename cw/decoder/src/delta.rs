@@ -0,0 +1,146 @@
+use indexmap::IndexSet;
+
+use crate::{DecodeError, Packet, PayloadType};
+
+// a delta frame lists only the cells that flipped, born or died, between turn `header.turn - 1`
+// and `header.turn`. the receiver rebuilds the board by flipping those cells in its copy of the
+// previous turn. if it doesn't hold that turn it asks for a full board with a `Keyframe` call.
+
+/// cells that differ between two generations
+pub fn diff(previous: &IndexSet<u32>, next: &IndexSet<u32>) -> IndexSet<u32> {
+    previous.symmetric_difference(next).copied().collect()
+}
+
+/// flips every cell of `flips` in `previous`
+pub fn apply(previous: &IndexSet<u32>, flips: &IndexSet<u32>) -> IndexSet<u32> {
+    previous.symmetric_difference(flips).copied().collect()
+}
+
+/// swaps the packet's cells for the flips against `base`, the board at `header.turn - 1`,
+/// when that comes out smaller on the wire than sending the whole board
+pub fn delta_against(packet: &mut Packet, base: &IndexSet<u32>) {
+    let flips = diff(base, &packet.cells);
+    if packet.delta_len(flips.len()) < packet.full_len(packet.cells.len()) {
+        packet.cells = flips;
+        packet.header.payload_type = PayloadType::Delta;
+    }
+}
+
+/// the latest generation seen on a connection, which incoming deltas are applied to
+#[derive(Debug, Default)]
+pub struct Generations {
+    latest: Option<(u32, IndexSet<u32>)>,
+}
+
+impl Generations {
+    pub fn record(&mut self, turn: u32, cells: IndexSet<u32>) {
+        self.latest = Some((turn, cells));
+    }
+
+    pub fn latest(&self) -> Option<(u32, &IndexSet<u32>)> {
+        self.latest.as_ref().map(|(turn, cells)| (*turn, cells))
+    }
+
+    /// forgets the latest generation so the next delta has to be preceded by a keyframe
+    pub fn reset(&mut self) {
+        self.latest = None;
+    }
+
+    /// turns a delta packet back into the full board and records it as the latest generation.
+    /// fails with `MissingKeyframe` if the turn the delta was taken against isn't the latest one
+    pub fn resolve(&mut self, packet: &mut Packet) -> Result<(), DecodeError> {
+        if packet.header.payload_type == PayloadType::Delta {
+            let base_turn = packet.header.turn.wrapping_sub(1);
+            let base = match &self.latest {
+                Some((turn, cells)) if *turn == base_turn => cells,
+                _ => return Err(DecodeError::MissingKeyframe(base_turn)),
+            };
+            packet.cells = apply(base, &packet.cells);
+            packet.header.payload_type = PayloadType::Sparse;
+        }
+        self.record(packet.header.turn, packet.cells.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(turn: u32, cells: &[u32]) -> Packet {
+        let mut packet = Packet::new();
+        packet.header.width = 64;
+        packet.header.height = 64;
+        packet.header.turn = turn;
+        packet.cells = cells.iter().copied().collect();
+        packet
+    }
+
+    #[test]
+    fn delta_resolves_against_the_previous_turn() {
+        let mut generations = Generations::default();
+        let mut first = board(4, &[1, 2, 3]);
+        generations.resolve(&mut first).unwrap();
+        assert_eq!(generations.latest(), Some((4, &first.cells)));
+
+        let mut delta = board(5, &[3, 7]);
+        delta.header.payload_type = PayloadType::Delta;
+        generations.resolve(&mut delta).unwrap();
+        assert_eq!(delta.header.payload_type, PayloadType::Sparse);
+        let mut cells: Vec<u32> = delta.cells.iter().copied().collect();
+        cells.sort_unstable();
+        assert_eq!(cells, [1, 2, 7]);
+        assert_eq!(generations.latest(), Some((5, &delta.cells)));
+    }
+
+    #[test]
+    fn delta_against_another_turn_needs_a_keyframe() {
+        let mut generations = Generations::default();
+        let mut delta = board(5, &[3]);
+        delta.header.payload_type = PayloadType::Delta;
+        assert!(matches!(
+            generations.resolve(&mut delta.clone()),
+            Err(DecodeError::MissingKeyframe(4))
+        ));
+
+        generations.record(3, IndexSet::from([1]));
+        assert!(matches!(
+            generations.resolve(&mut delta.clone()),
+            Err(DecodeError::MissingKeyframe(4))
+        ));
+        generations.record(4, IndexSet::from([1]));
+        generations.reset();
+        assert!(matches!(
+            generations.resolve(&mut delta),
+            Err(DecodeError::MissingKeyframe(4))
+        ));
+    }
+
+    #[test]
+    fn delta_wraps_back_from_turn_0() {
+        let mut generations = Generations::default();
+        generations.record(u32::MAX, IndexSet::from([1]));
+        let mut delta = board(0, &[2]);
+        delta.header.payload_type = PayloadType::Delta;
+        generations.resolve(&mut delta).unwrap();
+        assert_eq!(delta.cells, IndexSet::from([1, 2]));
+    }
+
+    #[test]
+    fn delta_against_only_when_it_is_smaller() {
+        let base: IndexSet<u32> = (0..100).collect();
+
+        // one cell flipped, the delta is far smaller than the 100 cells
+        let mut packet = board(1, &[]);
+        packet.cells = (0..101).collect();
+        delta_against(&mut packet, &base);
+        assert_eq!(packet.header.payload_type, PayloadType::Delta);
+        assert_eq!(packet.cells, IndexSet::from([100]));
+
+        // every cell flipped, sending the 2 left alive beats sending 102 flips
+        let mut packet = board(1, &[200, 201]);
+        delta_against(&mut packet, &base);
+        assert_eq!(packet.header.payload_type, PayloadType::Sparse);
+        assert_eq!(packet.cells, IndexSet::from([200, 201]));
+    }
+}
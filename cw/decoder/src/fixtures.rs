@@ -0,0 +1,43 @@
+use bytes::BytesMut;
+use tokio_util::codec::Encoder;
+
+use crate::codec::PacketCodec;
+use crate::{Packet, PayloadType, HEADER_SIZE_BYTES, MAX_PAYLOAD_BYTES};
+
+// boards shared by tests in more than one module
+
+/// `count` cells scattered over a 2048x2048 board and sent as a delta under msg_id 7. their 22 bit
+/// coordinates take 2 fragments for 20000 cells and 4 for 40000
+pub(crate) fn fragmented_board(count: u32) -> Packet {
+    let mut packet = Packet::new();
+    packet.header.msg_id = 7;
+    packet.header.width = 2048;
+    packet.header.height = 2048;
+    packet.header.payload_type = PayloadType::Delta;
+    packet.cells = (0..count)
+        .map(|i| i.wrapping_mul(2654435761) >> 10)
+        .collect();
+    packet
+}
+
+/// `packet` as it goes on the wire
+pub(crate) fn encode(packet: Packet) -> BytesMut {
+    let mut encoded = BytesMut::new();
+    PacketCodec::new().encode(packet, &mut encoded).unwrap();
+    encoded
+}
+
+/// the frames of `encoded`, one per fragment
+pub(crate) fn frames(mut encoded: BytesMut) -> Vec<BytesMut> {
+    let mut frames = Vec::new();
+    while !encoded.is_empty() {
+        let length = (encoded.len() - HEADER_SIZE_BYTES).min(MAX_PAYLOAD_BYTES);
+        frames.push(encoded.split_to(HEADER_SIZE_BYTES + length));
+    }
+    frames
+}
+
+/// the first frame of a message that needs 2
+pub(crate) fn first_fragment() -> BytesMut {
+    frames(encode(fragmented_board(20_000))).swap_remove(0)
+}
@@ -0,0 +1,91 @@
+use indexmap::IndexSet;
+
+use crate::DecodeError;
+
+// the gap payload: cells sorted ascending, the first stored as is and every later one as its
+// distance from the previous cell minus one, each written as a LEB128 varint (7 bits per byte,
+// high bit set while more bytes follow). clustered cells have small gaps, so most take one byte
+// where the fixed-width packing spends a whole coordinate on every cell.
+
+pub fn encode(cells: &IndexSet<u32>) -> Vec<u8> {
+    let mut sorted: Vec<u32> = cells.iter().copied().collect();
+    sorted.sort_unstable();
+
+    let mut data = Vec::with_capacity(sorted.len());
+    let mut previous = None;
+    for cell in sorted {
+        let value = match previous {
+            // cells are unique, so every gap is at least 1
+            Some(previous) => cell - previous - 1,
+            None => cell,
+        };
+        write_varint(&mut data, value);
+        previous = Some(cell);
+    }
+    data
+}
+
+pub fn decode(data: &[u8]) -> Result<IndexSet<u32>, DecodeError> {
+    decode_checked(data, |_, _| Ok(()))
+}
+
+/// `decode`, passing each cell and the byte its varint starts at to `check` before keeping it.
+/// gaps are at least 1, so cells can't repeat and only need checking against the board
+pub fn decode_checked<F>(data: &[u8], mut check: F) -> Result<IndexSet<u32>, DecodeError>
+where
+    F: FnMut(u32, usize) -> Result<(), DecodeError>,
+{
+    let mut cells = IndexSet::with_capacity(data.len());
+    let mut position = 0;
+    let mut previous: Option<u32> = None;
+    while position < data.len() {
+        let start = position;
+        let value = read_varint(data, &mut position)?;
+        let cell = match previous {
+            Some(previous) => previous
+                .checked_add(value)
+                .and_then(|cell| cell.checked_add(1))
+                .ok_or(DecodeError::MalformedPayload {
+                    offset: start,
+                    reason: "gap overflows 32 bits",
+                })?,
+            None => value,
+        };
+        check(cell, start)?;
+        cells.insert(cell);
+        previous = Some(cell);
+    }
+    Ok(cells)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> Result<u32, DecodeError> {
+    let start = *position;
+    let mut value: u32 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*position).ok_or(DecodeError::MalformedPayload {
+            offset: start,
+            reason: "varint cut off by the end of the payload",
+        })?;
+        *position += 1;
+        if shift > 28 || (shift == 28 && byte > 0x0f) {
+            return Err(DecodeError::MalformedPayload {
+                offset: start,
+                reason: "varint doesn't fit in 32 bits",
+            });
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,124 @@
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+use crate::codec::PacketCodec;
+use crate::{DecodeError, FunctionCall, Packet, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+// each side advertises the newest version it speaks in the header version byte of a handshake
+// frame. the accepting side answers with the version both will use, the older of the two, or
+// with version 0 if that is older than it can decode. versions in between are assumed supported.
+
+/// picks the version to use with a peer whose newest version is `peer_version`,
+/// or `None` if there isn't one both sides understand
+pub fn negotiate(peer_version: u8) -> Option<u8> {
+    let version = peer_version.min(PROTOCOL_VERSION);
+    (version >= MIN_PROTOCOL_VERSION).then_some(version)
+}
+
+/// opens a connection: offers our newest version and switches the codec to the one the peer picks
+pub async fn handshake<T>(framed: &mut Framed<T, PacketCodec>) -> Result<u8, DecodeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    framed.send(handshake_packet(PROTOCOL_VERSION)).await?;
+
+    let reply = receive_handshake(framed).await?;
+    let version = reply.header.version;
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    framed.codec_mut().set_version(version);
+    Ok(version)
+}
+
+/// answers a peer's handshake, rejecting it if there is no version both sides understand
+pub async fn accept_handshake<T>(framed: &mut Framed<T, PacketCodec>) -> Result<u8, DecodeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let offer = receive_handshake(framed).await?;
+    match negotiate(offer.header.version) {
+        Some(version) => {
+            framed.send(handshake_packet(version)).await?;
+            framed.codec_mut().set_version(version);
+            Ok(version)
+        }
+        None => {
+            framed.send(handshake_packet(0)).await?;
+            Err(DecodeError::UnsupportedVersion(offer.header.version))
+        }
+    }
+}
+
+fn handshake_packet(version: u8) -> Packet {
+    let mut packet = Packet::new();
+    packet.header.fn_call = FunctionCall::Handshake;
+    packet.header.version = version;
+    packet
+}
+
+async fn receive_handshake<T>(framed: &mut Framed<T, PacketCodec>) -> Result<Packet, DecodeError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let packet = match framed.next().await {
+        Some(packet) => packet?,
+        None => {
+            return Err(DecodeError::Other(
+                "connection closed during handshake".to_string(),
+            ))
+        }
+    };
+    if packet.header.fn_call != FunctionCall::Handshake {
+        return Err(DecodeError::Other(format!(
+            "expected a handshake, got {:?}",
+            packet.header.fn_call
+        )));
+    }
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn newer_peer_negotiates_down() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = Framed::new(client, PacketCodec::new());
+        let mut server = Framed::new(server, PacketCodec::new());
+
+        client
+            .send(handshake_packet(PROTOCOL_VERSION + 1))
+            .await
+            .unwrap();
+        assert_eq!(
+            accept_handshake(&mut server).await.unwrap(),
+            PROTOCOL_VERSION
+        );
+
+        let reply = client.next().await.unwrap().unwrap();
+        assert_eq!(reply.header.fn_call, FunctionCall::Handshake);
+        assert_eq!(reply.header.version, PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn handshake_agrees_on_a_version() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = Framed::new(client, PacketCodec::new());
+        let mut server = Framed::new(server, PacketCodec::new());
+
+        let (offered, accepted) =
+            tokio::join!(handshake(&mut client), accept_handshake(&mut server));
+        assert_eq!(offered.unwrap(), PROTOCOL_VERSION);
+        assert_eq!(accepted.unwrap(), PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn negotiate_picks_the_older_version() {
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION - 1), None);
+    }
+}
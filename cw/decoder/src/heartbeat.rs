@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use crate::{FunctionCall, Packet};
+
+// the broker pings every worker connection with an empty heartbeat request every `interval` and
+// the worker echoes it back under the same msg_id. a worker that hasn't answered within `timeout`
+// is declared dead, which fails its in-flight requests so their slices can go to another worker.
+// the worker in turn gives up on a broker it hasn't heard anything from in `interval + timeout`.
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// how often heartbeats are sent and how long a peer may take to answer one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Heartbeat {
+    /// the longest the pinged side can go without hearing from the pinging side
+    pub fn silence_limit(&self) -> Duration {
+        self.interval + self.timeout
+    }
+}
+
+/// an empty heartbeat frame
+pub fn heartbeat_packet() -> Packet {
+    let mut packet = Packet::new();
+    packet.header.fn_call = FunctionCall::Heartbeat;
+    packet
+}
@@ -0,0 +1,1342 @@
+//! the wire protocol shared by the controller, broker and workers: the frame `Header`,
+//! `Packet` and its payload encodings, the tokio codec and client, and the broker and worker
+//! themselves. see protocol.md for the layout on the wire.
+
+use board::Board;
+use bytes::{BufMut, BytesMut};
+use indexmap::IndexSet;
+use packing::Accumulator;
+use reassembly::Reassembly;
+use std::fmt;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub mod bitmap;
+pub mod board;
+pub mod broker;
+pub mod checksum;
+pub mod client;
+pub mod codec;
+pub mod delta;
+#[cfg(test)]
+mod fixtures;
+pub mod gaps;
+pub mod handshake;
+pub mod heartbeat;
+pub mod packing;
+mod reassembly;
+mod stream;
+pub mod worker;
+
+// originally used standard hashset but doesnt have order
+// index set retains order of insertion
+// this increases decode time by about 30-40% but i believe it is a worthy tradeoff
+
+const BYTE: usize = 8;
+
+// header layout, see protocol.md. each field starts where the previous one ends and is big endian
+const VERSION: usize = 0;
+const VERSION_BYTES: usize = 1;
+const FUNCTION_CALL: usize = VERSION + VERSION_BYTES;
+const FUNCTION_CALL_BYTES: usize = 1;
+const PAYLOAD_TYPE: usize = FUNCTION_CALL + FUNCTION_CALL_BYTES;
+const PAYLOAD_TYPE_BYTES: usize = 1;
+const MESSAGE_ID: usize = PAYLOAD_TYPE + PAYLOAD_TYPE_BYTES;
+const MESSAGE_ID_BYTES: usize = 2;
+const WIDTH: usize = MESSAGE_ID + MESSAGE_ID_BYTES;
+const WIDTH_BYTES: usize = 2;
+const HEIGHT: usize = WIDTH + WIDTH_BYTES;
+const HEIGHT_BYTES: usize = 2;
+const TURN: usize = HEIGHT + HEIGHT_BYTES;
+const TURN_BYTES: usize = 4;
+const LENGTH: usize = TURN + TURN_BYTES;
+const LENGTH_BYTES: usize = 3;
+const CELL_COUNT: usize = LENGTH + LENGTH_BYTES;
+const CELL_COUNT_BYTES: usize = 4;
+const FRAGMENT: usize = CELL_COUNT + CELL_COUNT_BYTES;
+const FRAGMENT_BYTES: usize = 2;
+const FRAGMENT_COUNT: usize = FRAGMENT + FRAGMENT_BYTES;
+const FRAGMENT_COUNT_BYTES: usize = 2;
+const CHECKSUM: usize = FRAGMENT_COUNT + FRAGMENT_COUNT_BYTES;
+const CHECKSUM_BYTES: usize = 2;
+pub const HEADER_SIZE_BYTES: usize = CHECKSUM + CHECKSUM_BYTES;
+
+/// newest protocol version this side speaks, sent in the handshake and stamped on every frame
+pub const PROTOCOL_VERSION: u8 = 1;
+/// oldest protocol version this side can still decode
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// largest payload a single frame may carry, bigger payloads are split over several frames
+pub const MAX_PAYLOAD_BYTES: usize = 32 * 1024;
+
+/// largest payload a whole message may carry over all its fragments. enough for the bitmap of the
+/// biggest board a header can describe, 65535x65535
+pub const MAX_MESSAGE_BYTES: usize = 512 * 1024 * 1024;
+
+/// how long a fragmented message may go without another of its fragments arriving before it is dropped
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// everything that can go wrong reading or writing a frame. where the problem is at a known place
+/// in the frame the message says where: header fields by their byte in the header, payload
+/// problems by their byte offset into the payload
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(std::io::Error),
+    /// the stream ended `received` bytes into a header
+    TruncatedHeader {
+        received: usize,
+    },
+    /// a payload was `received` bytes long where the header called for `expected`
+    LengthMismatch {
+        expected: usize,
+        received: usize,
+    },
+    BadChecksum {
+        expected: u16,
+        computed: u16,
+    },
+    UnknownFunctionCall(u8),
+    UnknownPayloadType(u8),
+    MissingKeyframe(u32),
+    UnsupportedVersion(u8),
+    PayloadTooLarge {
+        length: usize,
+        max: usize,
+    },
+    MissingFragments {
+        msg_id: u16,
+        received: u16,
+        expected: u16,
+    },
+    /// a fragment that doesn't carry on from the last one received of its message
+    UnexpectedFragment {
+        msg_id: u16,
+        fragment: u16,
+        fragment_count: u16,
+    },
+    /// the payload held a different number of cells to the header's cell count
+    CellCountMismatch {
+        expected: u32,
+        decoded: usize,
+    },
+    /// cell (`x`, `y`) lies outside the board, at byte `offset` of the payload
+    CoordinateOutOfRange {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        offset: usize,
+    },
+    /// cell (`x`, `y`) turned up a second time, at byte `offset` of the payload
+    DuplicateCell {
+        x: u32,
+        y: u32,
+        offset: usize,
+    },
+    /// the payload can't be decoded from byte `offset` on
+    MalformedPayload {
+        offset: usize,
+        reason: &'static str,
+    },
+    Other(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "IO error: {}", e),
+            DecodeError::TruncatedHeader { received } => write!(
+                f,
+                "Truncated header: stream ended after {} of {} bytes",
+                received, HEADER_SIZE_BYTES
+            ),
+            DecodeError::LengthMismatch { expected, received } => write!(
+                f,
+                "Length mismatch at header byte {}: expected a payload of {} bytes, got {}",
+                LENGTH, expected, received
+            ),
+            DecodeError::BadChecksum { expected, computed } => write!(
+                f,
+                "Checksum mismatch at header byte {}: header says {:#06x}, frame hashes to {:#06x}",
+                CHECKSUM, expected, computed
+            ),
+            DecodeError::UnknownFunctionCall(value) => write!(
+                f,
+                "Unknown function call at header byte {}: {}",
+                FUNCTION_CALL, value
+            ),
+            DecodeError::UnknownPayloadType(value) => write!(
+                f,
+                "Unknown payload type at header byte {}: {}",
+                PAYLOAD_TYPE, value
+            ),
+            DecodeError::MissingKeyframe(turn) => {
+                write!(f, "Delta frame needs turn {} as a keyframe", turn)
+            }
+            DecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported protocol version at header byte {}: {}",
+                VERSION, version
+            ),
+            DecodeError::PayloadTooLarge { length, max } => write!(
+                f,
+                "Payload too large at header byte {}: {} bytes is over the limit of {} bytes",
+                LENGTH, length, max
+            ),
+            DecodeError::MissingFragments {
+                msg_id,
+                received,
+                expected,
+            } => write!(
+                f,
+                "Message {} is missing fragments, got {} of {}",
+                msg_id, received, expected
+            ),
+            DecodeError::UnexpectedFragment {
+                msg_id,
+                fragment,
+                fragment_count,
+            } => write!(
+                f,
+                "Unexpected fragment at header byte {}: fragment {} of {} for message {}",
+                FRAGMENT, fragment, fragment_count, msg_id
+            ),
+            DecodeError::CellCountMismatch { expected, decoded } => write!(
+                f,
+                "Cell count mismatch at header byte {}: header says {}, payload holds {}",
+                CELL_COUNT, expected, decoded
+            ),
+            DecodeError::CoordinateOutOfRange {
+                x,
+                y,
+                width,
+                height,
+                offset,
+            } => write!(
+                f,
+                "Coordinate out of range at payload byte {}: ({}, {}) is outside the {}x{} board",
+                offset, x, y, width, height
+            ),
+            DecodeError::DuplicateCell { x, y, offset } => write!(
+                f,
+                "Duplicate cell at payload byte {}: ({}, {}) was already decoded",
+                offset, x, y
+            ),
+            DecodeError::MalformedPayload { offset, reason } => {
+                write!(f, "Malformed payload at byte {}: {}", offset, reason)
+            }
+            DecodeError::Other(msg) => write!(f, "Error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+// #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+// struct Cell {
+//     xy: u32,
+// }
+/// life operations on a set of packed `x << y_bits | y` coordinates
+/// on a `width` x `height` board that wraps round at every edge
+pub trait Cell {
+    /// number of live cells around the `index`th cell of the set
+    fn neighbours(&self, index: usize, width: u32, height: u32) -> usize;
+    /// number of live cells around `xy`, which need not be alive itself
+    fn live_neighbours(&self, xy: u32, width: u32, height: u32) -> usize;
+}
+
+impl<B: Board> Cell for B {
+    fn neighbours(&self, index: usize, width: u32, height: u32) -> usize {
+        let xy = self.nth(index).unwrap();
+        self.live_neighbours(xy, width, height)
+    }
+
+    fn live_neighbours(&self, xy: u32, width: u32, height: u32) -> usize {
+        neighbour_positions(xy, width, height)
+            .iter()
+            .filter(|pos| self.contains(**pos))
+            .count()
+    }
+}
+
+/// packed coordinates of the 8 cells around `xy` on a toroidal `width` x `height` board
+pub fn neighbour_positions(xy: u32, width: u32, height: u32) -> [u32; 8] {
+    let y_bits = coord_bits(height);
+    let x = xy >> y_bits;
+    let y = xy & ((1 << y_bits) - 1);
+
+    // adding `width - 1` steps back one without underflowing at 0
+    let left = (x + width - 1) % width;
+    let right = (x + 1) % width;
+    let down = (y + height - 1) % height;
+    let up = (y + 1) % height;
+    let pack = |x: u32, y: u32| x << y_bits | y;
+
+    [
+        pack(right, y),    // right
+        pack(left, y),     // left
+        pack(x, up),       // up
+        pack(x, down),     // down
+        pack(right, up),   // right up
+        pack(right, down), // right down
+        pack(left, down),  // left down
+        pack(left, up),    // left up
+    ]
+}
+
+/// bits needed to store every coordinate in `0..size`, ceil(log2(size)) but at least 1
+pub fn coord_bits(size: u32) -> u32 {
+    (32 - size.saturating_sub(1).leading_zeros()).max(1)
+}
+
+/// operation carried in the header `fn_call` byte, telling the receiver what to do with the payload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum FunctionCall {
+    /// compute the next turn for the slice of the board in the payload
+    #[default]
+    ProcessSlice = 0,
+    /// report how many cells are alive
+    AliveCellCount = 1,
+    /// pause or resume processing
+    Pause = 2,
+    /// stop processing and close the connection
+    Quit = 3,
+    /// send back the current state of the board
+    Snapshot = 4,
+    /// liveness check between brokers and workers, carries no payload
+    Heartbeat = 5,
+    /// version negotiation when a connection opens, carries no payload
+    Handshake = 6,
+    /// asks for the full board instead of a delta, or answers a delta whose base turn is unknown
+    Keyframe = 7,
+    /// answers a request that couldn't be carried out, such as a turn with no workers to run it.
+    /// keeps the request's msg_id and turn so it can be sent again, carries no payload
+    Failed = 8,
+}
+
+impl TryFrom<u8> for FunctionCall {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, DecodeError> {
+        match value {
+            0 => Ok(FunctionCall::ProcessSlice),
+            1 => Ok(FunctionCall::AliveCellCount),
+            2 => Ok(FunctionCall::Pause),
+            3 => Ok(FunctionCall::Quit),
+            4 => Ok(FunctionCall::Snapshot),
+            5 => Ok(FunctionCall::Heartbeat),
+            6 => Ok(FunctionCall::Handshake),
+            7 => Ok(FunctionCall::Keyframe),
+            8 => Ok(FunctionCall::Failed),
+            _ => Err(DecodeError::UnknownFunctionCall(value)),
+        }
+    }
+}
+
+impl From<FunctionCall> for u8 {
+    fn from(fn_call: FunctionCall) -> Self {
+        fn_call as u8
+    }
+}
+
+/// how the cells in the payload are laid out, carried in the header `payload_type` byte
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PayloadType {
+    /// list of bit-packed coordinates, one per live cell
+    #[default]
+    Sparse = 0,
+    /// one bit per cell of the board, see `bitmap`
+    Dense = 1,
+    /// packed coordinates of the cells that flipped since the previous turn, see `delta`
+    Delta = 2,
+    /// sorted cells stored as varint gaps, see `gaps`. only used when asked for
+    Gaps = 3,
+}
+
+impl TryFrom<u8> for PayloadType {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, DecodeError> {
+        match value {
+            0 => Ok(PayloadType::Sparse),
+            1 => Ok(PayloadType::Dense),
+            2 => Ok(PayloadType::Delta),
+            3 => Ok(PayloadType::Gaps),
+            _ => Err(DecodeError::UnknownPayloadType(value)),
+        }
+    }
+}
+
+impl From<PayloadType> for u8 {
+    fn from(payload_type: PayloadType) -> Self {
+        payload_type as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub fn_call: FunctionCall,
+    pub payload_type: PayloadType,
+    pub msg_id: u16,
+    pub width: u16,
+    pub height: u16,
+    pub turn: u32,
+    pub length: u32,
+    /// cells in the payload, so the padding that ends a coordinate list is never read as a cell
+    pub cell_count: u32,
+    /// position of this frame among the frames a large payload is split over, from 0
+    pub fragment: u16,
+    pub fragment_count: u16,
+    pub checksum: u16,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Header {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            fn_call: FunctionCall::ProcessSlice,
+            payload_type: PayloadType::Sparse,
+            msg_id: 0,
+            width: 0,
+            height: 0,
+            turn: 0,
+            length: 0,
+            cell_count: 0,
+            fragment: 0,
+            fragment_count: 1,
+            checksum: 0,
+        }
+    }
+
+    /// serialises the header into its big-endian wire layout, the inverse of `Packet::decode_header`
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE_BYTES] {
+        let mut data = [0u8; HEADER_SIZE_BYTES];
+        data[VERSION] = self.version;
+        data[FUNCTION_CALL] = self.fn_call.into();
+        data[PAYLOAD_TYPE] = self.payload_type.into();
+        data[MESSAGE_ID..MESSAGE_ID + MESSAGE_ID_BYTES].copy_from_slice(&self.msg_id.to_be_bytes());
+        data[WIDTH..WIDTH + WIDTH_BYTES].copy_from_slice(&self.width.to_be_bytes());
+        data[HEIGHT..HEIGHT + HEIGHT_BYTES].copy_from_slice(&self.height.to_be_bytes());
+        data[TURN..TURN + TURN_BYTES].copy_from_slice(&self.turn.to_be_bytes());
+        // only the low bytes of the length fit in the field
+        data[LENGTH..LENGTH + LENGTH_BYTES]
+            .copy_from_slice(&self.length.to_be_bytes()[4 - LENGTH_BYTES..]);
+        data[CELL_COUNT..CELL_COUNT + CELL_COUNT_BYTES]
+            .copy_from_slice(&self.cell_count.to_be_bytes());
+        data[FRAGMENT..FRAGMENT + FRAGMENT_BYTES].copy_from_slice(&self.fragment.to_be_bytes());
+        data[FRAGMENT_COUNT..FRAGMENT_COUNT + FRAGMENT_COUNT_BYTES]
+            .copy_from_slice(&self.fragment_count.to_be_bytes());
+        data[CHECKSUM..CHECKSUM + CHECKSUM_BYTES].copy_from_slice(&self.checksum.to_be_bytes());
+        data
+    }
+
+    /// checks the frame was written in the version agreed for the connection.
+    /// handshake frames are exempt as they are what carries the versions being agreed on
+    pub fn check_version(&self, negotiated: u8) -> Result<(), DecodeError> {
+        if self.fn_call != FunctionCall::Handshake && self.version != negotiated {
+            return Err(DecodeError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+
+    /// checks the frame's payload is within the per frame limit and its fragment number is in
+    /// range, so an oversized frame is turned away before any of its payload is buffered
+    pub fn check_frame(&self) -> Result<(), DecodeError> {
+        if self.length as usize > MAX_PAYLOAD_BYTES {
+            return Err(DecodeError::PayloadTooLarge {
+                length: self.length as usize,
+                max: MAX_PAYLOAD_BYTES,
+            });
+        }
+        if self.fragment >= self.fragment_count {
+            return Err(self.unexpected_fragment());
+        }
+        Ok(())
+    }
+
+    /// checks this frame carries on from `previous`, the last fragment received of the same
+    /// message, or starts a new message if there is none. fragments are always sent in order
+    pub fn check_sequence(&self, previous: Option<&Header>) -> Result<(), DecodeError> {
+        let in_order = match previous {
+            Some(previous) => {
+                self.msg_id == previous.msg_id
+                    && self.fragment_count == previous.fragment_count
+                    && self.fragment == previous.fragment + 1
+            }
+            None => self.fragment == 0,
+        };
+        if !in_order {
+            return Err(self.unexpected_fragment());
+        }
+        Ok(())
+    }
+
+    pub fn is_last_fragment(&self) -> bool {
+        self.fragment + 1 == self.fragment_count
+    }
+
+    fn unexpected_fragment(&self) -> DecodeError {
+        DecodeError::UnexpectedFragment {
+            msg_id: self.msg_id,
+            fragment: self.fragment,
+            fragment_count: self.fragment_count,
+        }
+    }
+
+    /// the error for a message that stopped after this fragment
+    pub fn missing_fragments(&self) -> DecodeError {
+        DecodeError::MissingFragments {
+            msg_id: self.msg_id,
+            received: self.fragment + 1,
+            expected: self.fragment_count,
+        }
+    }
+
+    /// appends the serialised header to `dst`
+    pub fn encode<B: BufMut>(&self, dst: &mut B) {
+        dst.put_slice(&self.to_bytes());
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Packet {
+    pub header: Header,
+    pub cells: IndexSet<u32>,
+}
+
+impl Packet {
+    pub fn new() -> Self {
+        Self {
+            header: Header::new(),
+            cells: IndexSet::new(),
+        }
+    }
+
+    fn decode_header(&mut self, data: &[u8]) -> Result<(), DecodeError> {
+        self.header = Header {
+            version: data[VERSION],
+            fn_call: FunctionCall::try_from(data[FUNCTION_CALL])?,
+            payload_type: PayloadType::try_from(data[PAYLOAD_TYPE])?,
+            msg_id: ((data[MESSAGE_ID] as u16) << BYTE | (data[MESSAGE_ID + 1] as u16)),
+            width: ((data[WIDTH] as u16) << BYTE | (data[WIDTH + 1] as u16)),
+            height: ((data[HEIGHT] as u16) << BYTE | (data[HEIGHT + 1] as u16)),
+            turn: data[TURN..TURN + TURN_BYTES]
+                .iter()
+                .fold(0, |buf, byte| buf << BYTE | *byte as u32),
+            length: data[LENGTH..LENGTH + LENGTH_BYTES]
+                .iter()
+                .fold(0, |buf, byte| buf << BYTE | *byte as u32),
+            cell_count: data[CELL_COUNT..CELL_COUNT + CELL_COUNT_BYTES]
+                .iter()
+                .fold(0, |buf, byte| buf << BYTE | *byte as u32),
+            fragment: ((data[FRAGMENT] as u16) << BYTE | (data[FRAGMENT + 1] as u16)),
+            fragment_count: ((data[FRAGMENT_COUNT] as u16) << BYTE
+                | (data[FRAGMENT_COUNT + 1] as u16)),
+            checksum: ((data[CHECKSUM] as u16) << BYTE | (data[CHECKSUM + 1] as u16)),
+        };
+        Ok(())
+    }
+
+    /// decodes and checks the header of the next fragment of the message in `reassembly`
+    fn decode_frame_header(
+        &mut self,
+        header: &[u8],
+        reassembly: &Reassembly,
+    ) -> Result<(), DecodeError> {
+        self.decode_header(header)?;
+        self.header.check_version(PROTOCOL_VERSION)?;
+        self.header.check_frame()?;
+        reassembly.check(&self.header)
+    }
+
+    /// decodes exactly `cell_count` packed cells into whichever board type the caller wants,
+    /// ignoring the padding after the last one
+    pub fn decode_payload<B: Board>(
+        &self,
+        data: &[u8],
+        coordinate_length: u32,
+        cell_count: usize,
+    ) -> B {
+        let mut cells = B::for_board(
+            self.header.width as u32,
+            self.header.height as u32,
+            cell_count,
+        );
+        packing::unpack_words(data, coordinate_length, cell_count, |cell: u64| {
+            cells.insert(cell as u32);
+        });
+        cells
+    }
+
+    /// `decode_payload` without the `IndexSet`, for callers that don't need duplicates dropped.
+    /// cells come back in payload order, which skips the hashing the set costs on every insert
+    pub fn decode_payload_vec(
+        &self,
+        data: &[u8],
+        coordinate_length: u32,
+        cell_count: usize,
+    ) -> Vec<u32> {
+        let mut cells = Vec::with_capacity(cell_count);
+        packing::unpack_words(data, coordinate_length, cell_count, |cell: u64| {
+            cells.push(cell as u32)
+        });
+        cells
+    }
+
+    /// `decode_payload_vec` for values of any width the accumulator `A` can take, up to
+    /// `packing::max_bits::<A>()`. the cells of a board over 65536x65536, or values carrying more
+    /// bits per cell than a coordinate, don't fit in a u32 and come back here as they were packed
+    pub fn decode_values<A: Accumulator>(&self, data: &[u8], bits: u32, count: usize) -> Vec<A> {
+        let mut values = Vec::with_capacity(count);
+        packing::unpack_words(data, bits, count, |value| values.push(value));
+        values
+    }
+
+    /// `decode_payload` in strict mode: every cell has to lie on the board and appear only once,
+    /// where the lenient decode keeps whatever comes out and lets the board drop duplicates
+    pub fn decode_payload_strict<B: Board>(
+        &self,
+        data: &[u8],
+        coordinate_length: u32,
+        cell_count: usize,
+    ) -> Result<B, DecodeError> {
+        let mut cells = B::for_board(
+            self.header.width as u32,
+            self.header.height as u32,
+            cell_count,
+        );
+        let mut result = Ok(());
+        let mut index = 0;
+        // unpacking can't stop part way, so everything after the first bad cell is skipped
+        packing::unpack_words(data, coordinate_length, cell_count, |cell: u64| {
+            let cell = cell as u32;
+            if result.is_ok() {
+                let offset = index * coordinate_length as usize / BYTE;
+                result = self.check_cell(cell, offset).and_then(|()| {
+                    if cells.insert(cell) {
+                        Ok(())
+                    } else {
+                        let (x, y) = self.split_cell(cell);
+                        Err(DecodeError::DuplicateCell { x, y, offset })
+                    }
+                });
+            }
+            index += 1;
+        });
+        result.map(|()| cells)
+    }
+
+    /// fails if `cell`, found at byte `offset` of the payload, is off the board
+    fn check_cell(&self, cell: u32, offset: usize) -> Result<(), DecodeError> {
+        let (x, y) = self.split_cell(cell);
+        let (width, height) = (self.header.width as u32, self.header.height as u32);
+        if x >= width || y >= height {
+            return Err(DecodeError::CoordinateOutOfRange {
+                x,
+                y,
+                width,
+                height,
+                offset,
+            });
+        }
+        Ok(())
+    }
+
+    /// a packed cell as its (x, y)
+    fn split_cell(&self, cell: u32) -> (u32, u32) {
+        let y_bits = coord_bits(self.header.height as u32);
+        (cell >> y_bits, cell & ((1 << y_bits) - 1))
+    }
+
+    /// reads one whole message from `stream`: for every fragment, the fixed size header
+    /// followed by exactly `header.length` payload bytes.
+    ///
+    /// `read_full` takes care of short reads, and because nothing past the end of the last
+    /// fragment is consumed, any frame coalesced behind it is left in the stream for the next call.
+    /// returns an empty set if the peer closed the connection before sending a header.
+    pub async fn decode<R>(&mut self, stream: &mut R) -> Result<IndexSet<u32>, DecodeError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.read_message(stream, false).await
+    }
+
+    /// `decode` in strict mode, failing on cells off the board or repeated rather than keeping them
+    pub async fn decode_strict<R>(&mut self, stream: &mut R) -> Result<IndexSet<u32>, DecodeError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.read_message(stream, true).await
+    }
+
+    async fn read_message<R>(
+        &mut self,
+        stream: &mut R,
+        strict: bool,
+    ) -> Result<IndexSet<u32>, DecodeError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut buf = [0u8; HEADER_SIZE_BYTES];
+        let mut payload = BytesMut::new();
+        let mut reassembly = Reassembly::new(REASSEMBLY_TIMEOUT, MAX_MESSAGE_BYTES);
+        self.header = loop {
+            let fragment = self.read_fragment(stream, &mut buf, &mut payload, &reassembly);
+            if !reassembly.by_deadline(fragment).await? {
+                reassembly.stopped()?;
+                return Ok(IndexSet::new());
+            }
+            if let Some(header) = reassembly.add(self.header.clone()) {
+                break header;
+            }
+        };
+        self.decode_cells(&payload, strict)
+    }
+
+    /// reads the next fragment of the message in `reassembly` and appends its payload to
+    /// `payload`, returning false if the stream ended cleanly before its header
+    async fn read_fragment<R>(
+        &mut self,
+        stream: &mut R,
+        header: &mut [u8; HEADER_SIZE_BYTES],
+        payload: &mut BytesMut,
+        reassembly: &Reassembly,
+    ) -> Result<bool, DecodeError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if !read_header(stream, header).await? {
+            return Ok(false);
+        }
+        self.decode_frame_header(header, reassembly)?;
+
+        let start = payload.len();
+        payload.resize(start + self.header.length as usize, 0);
+        let received = read_full(stream, &mut payload[start..]).await?;
+        if received < self.header.length as usize {
+            return Err(DecodeError::LengthMismatch {
+                expected: self.header.length as usize,
+                received,
+            });
+        }
+        self.verify_checksum(header, &payload[start..])?;
+        Ok(true)
+    }
+
+    /// decodes the payload in whichever format the header version says it was written in.
+    /// `strict` rejects cells off the board and repeated cells, a bitmap can't hold either
+    fn decode_cells(&mut self, payload: &[u8], strict: bool) -> Result<IndexSet<u32>, DecodeError> {
+        // a handshake carries no cells, and its version is the one on offer rather than a payload
+        // format, so a peer offering a newer version than ours still gets to negotiate down
+        if self.header.fn_call == FunctionCall::Handshake {
+            return Ok(IndexSet::new());
+        }
+        let cell_count = self.header.cell_count as usize;
+        match (self.header.version, self.header.payload_type) {
+            (1, PayloadType::Sparse | PayloadType::Delta) => {
+                self.check_payload_len(payload.len())?;
+                let coordinate_length = self.coord_len();
+                if strict {
+                    self.decode_payload_strict(payload, coordinate_length, cell_count)
+                } else {
+                    Ok(self.decode_payload(payload, coordinate_length, cell_count))
+                }
+            }
+            (1, PayloadType::Dense) => {
+                self.check_payload_len(payload.len())?;
+                let (width, height) = (self.header.width as u32, self.header.height as u32);
+                self.check_cell_count(bitmap::decode(payload, width, height))
+            }
+            (1, PayloadType::Gaps) if strict => {
+                let cells =
+                    gaps::decode_checked(payload, |cell, offset| self.check_cell(cell, offset))?;
+                self.check_cell_count(cells)
+            }
+            (1, PayloadType::Gaps) => self.check_cell_count(gaps::decode(payload)?),
+            (version, _) => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// a coordinate list is exactly as long as its cells need, so padding can't add or hide one,
+    /// and a bitmap exactly as long as the board, so it can't be cut short or run on
+    fn check_payload_len(&self, payload_len: usize) -> Result<(), DecodeError> {
+        let expected = match self.header.payload_type {
+            PayloadType::Dense => self.dense_len(),
+            _ => self.delta_len(self.header.cell_count as usize),
+        };
+        if payload_len != expected {
+            return Err(DecodeError::LengthMismatch {
+                expected,
+                received: payload_len,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_cell_count(&self, cells: IndexSet<u32>) -> Result<IndexSet<u32>, DecodeError> {
+        if cells.len() != self.header.cell_count as usize {
+            return Err(DecodeError::CellCountMismatch {
+                expected: self.header.cell_count,
+                decoded: cells.len(),
+            });
+        }
+        Ok(cells)
+    }
+
+    /// encodes `cells` in the format of the header version, the inverse of `decode_cells`.
+    /// a delta is always sent as a coordinate list and gaps are used if the header asks for them,
+    /// otherwise the board goes as whichever of the coordinate list and the bitmap comes out
+    /// smaller, with the choice recorded in the header payload type
+    fn encode_cells(&mut self, cells: IndexSet<u32>) -> Result<Vec<u8>, DecodeError> {
+        if !matches!(
+            self.header.payload_type,
+            PayloadType::Delta | PayloadType::Gaps
+        ) {
+            self.header.payload_type = PayloadType::Sparse;
+        }
+        self.header.cell_count = cells.len() as u32;
+        if cells.is_empty() {
+            return Ok(Vec::new());
+        }
+        match self.header.version {
+            1 if self.header.payload_type == PayloadType::Gaps => Ok(gaps::encode(&cells)),
+            1 => {
+                let coordinate_length = self.coord_len();
+                if self.header.payload_type != PayloadType::Delta
+                    && self.dense_len() < self.delta_len(cells.len())
+                {
+                    self.header.payload_type = PayloadType::Dense;
+                    let (width, height) = (self.header.width as u32, self.header.height as u32);
+                    return bitmap::encode(&cells, width, height);
+                }
+                Ok(self.encode_payload(&cells, coordinate_length as usize))
+            }
+            version => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+
+    /// payload bytes for `cell_count` cells as a coordinate list, which is also how deltas are sent
+    pub fn delta_len(&self, cell_count: usize) -> usize {
+        let coordinate_length = self.coord_len();
+        packing::packed_len(cell_count, coordinate_length)
+    }
+
+    /// payload bytes for the board as a bitmap
+    pub fn dense_len(&self) -> usize {
+        bitmap::encoded_len(self.header.width as u32, self.header.height as u32)
+    }
+
+    /// payload bytes for a full board of `cell_count` cells in the smaller of the two formats
+    pub fn full_len(&self, cell_count: usize) -> usize {
+        self.delta_len(cell_count).min(self.dense_len())
+    }
+
+    /// checks the decoded header checksum against the CRC of the raw frame bytes
+    fn verify_checksum(&self, header: &[u8], payload: &[u8]) -> Result<(), DecodeError> {
+        let computed = checksum::frame_checksum(&header[..CHECKSUM], payload);
+        if computed != self.header.checksum {
+            return Err(DecodeError::BadChecksum {
+                expected: self.header.checksum,
+                computed,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn encode_payload<B: Board>(&self, cells: &B, coordinate_length: usize) -> Vec<u8> {
+        let mut data =
+            Vec::with_capacity(packing::packed_len(cells.len(), coordinate_length as u32));
+        packing::pack(
+            cells.cells().map(u64::from),
+            coordinate_length as u32,
+            &mut data,
+        );
+        data
+    }
+
+    /// bits per packed coordinate on this board, enough for an x and a y
+    pub fn coord_len(&self) -> u32 {
+        coord_bits(self.header.width as u32) + coord_bits(self.header.height as u32)
+    }
+}
+
+/// fills `buf` with the next header from `stream`, returning false if the stream ended cleanly
+/// before one started
+async fn read_header<R>(
+    stream: &mut R,
+    buf: &mut [u8; HEADER_SIZE_BYTES],
+) -> Result<bool, DecodeError>
+where
+    R: AsyncRead + Unpin,
+{
+    match read_full(stream, &mut buf[..]).await? {
+        0 => Ok(false),
+        HEADER_SIZE_BYTES => Ok(true),
+        received => Err(DecodeError::TruncatedHeader { received }),
+    }
+}
+
+/// reads until `buf` is full or the stream ends, returning how many bytes came in
+pub(crate) async fn read_full<R>(stream: &mut R, buf: &mut [u8]) -> Result<usize, DecodeError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut received = 0;
+    while received < buf.len() {
+        match stream.read(&mut buf[received..]).await? {
+            0 => break,
+            n => received += n,
+        }
+    }
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trips() {
+        let header = Header {
+            version: 0xA5,
+            fn_call: FunctionCall::Keyframe,
+            payload_type: PayloadType::Gaps,
+            msg_id: 0xBEEF,
+            width: 0x1234,
+            height: 0x0F0F,
+            turn: 0xDEAD_BEEF,
+            // all three bytes of the 24 bit field in use
+            length: 0xAB_CDEF,
+            cell_count: 0x0102_0304,
+            fragment: 0x0506,
+            fragment_count: 0x0708,
+            checksum: 0x9ABC,
+        };
+        let bytes = header.to_bytes();
+
+        let mut packet = Packet::new();
+        packet.decode_header(&bytes).unwrap();
+        assert_eq!(packet.header, header);
+        assert_eq!(packet.header.to_bytes(), bytes);
+
+        let mut encoded = BytesMut::new();
+        header.encode(&mut encoded);
+        assert_eq!(&encoded[..], &bytes[..]);
+    }
+
+    #[tokio::test]
+    async fn stream_ending_early_says_how_far_it_got() {
+        use tokio_util::codec::Encoder;
+
+        let mut packet = Packet::new();
+        packet.header.width = 8;
+        packet.header.height = 8;
+        packet.cells.extend([1, 9, 17]);
+        let mut encoded = BytesMut::new();
+        codec::PacketCodec::new()
+            .encode(packet, &mut encoded)
+            .unwrap();
+        let length = encoded.len() - HEADER_SIZE_BYTES;
+
+        let mut stream = &encoded[..10];
+        match Packet::new().decode(&mut stream).await {
+            Err(DecodeError::TruncatedHeader { received: 10 }) => {}
+            other => panic!("expected a truncated header, got {:?}", other),
+        }
+        let mut stream = &encoded[..encoded.len() - 1];
+        match Packet::new().decode(&mut stream).await {
+            Err(DecodeError::LengthMismatch { expected, received })
+                if expected == length && received == length - 1 => {}
+            other => panic!("expected a length mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_io_errors_have_a_source() {
+        use std::error::Error;
+
+        let io = DecodeError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert_eq!(
+            io.source().unwrap().to_string(),
+            std::io::Error::from(std::io::ErrorKind::UnexpectedEof).to_string()
+        );
+        let errors = [
+            DecodeError::TruncatedHeader { received: 3 },
+            DecodeError::LengthMismatch {
+                expected: 10,
+                received: 4,
+            },
+            DecodeError::BadChecksum {
+                expected: 1,
+                computed: 2,
+            },
+            DecodeError::CoordinateOutOfRange {
+                x: 9,
+                y: 0,
+                width: 8,
+                height: 8,
+                offset: 4,
+            },
+            DecodeError::DuplicateCell {
+                x: 1,
+                y: 1,
+                offset: 6,
+            },
+            DecodeError::MalformedPayload {
+                offset: 2,
+                reason: "varint runs past the end of the payload",
+            },
+        ];
+        for error in errors {
+            assert!(error.source().is_none(), "{:?}", error);
+            assert!(!error.to_string().is_empty());
+        }
+    }
+
+    /// a peer that sends the first fragment of a bigger message and then goes quiet,
+    /// keeping the connection open
+    async fn stalled_stream() -> (tokio::io::DuplexStream, tokio::io::DuplexStream) {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut writer, reader) = tokio::io::duplex(2 * MAX_PAYLOAD_BYTES);
+        writer.write_all(&fixtures::first_fragment()).await.unwrap();
+        (writer, reader)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decode_gives_up_on_missing_fragments() {
+        let (_writer, mut reader) = stalled_stream().await;
+        let result = Packet::new().decode(&mut reader).await;
+        assert!(
+            matches!(
+                result,
+                Err(DecodeError::MissingFragments { received: 1, .. })
+            ),
+            "{:?}",
+            result
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decode_with_gives_up_on_missing_fragments() {
+        let (_writer, mut reader) = stalled_stream().await;
+        let result = Packet::new().decode_with(&mut reader, |_| {}).await;
+        assert!(
+            matches!(
+                result,
+                Err(DecodeError::MissingFragments { received: 1, .. })
+            ),
+            "{:?}",
+            result
+        );
+    }
+
+    /// a peer that sends `packet` a fragment at a time, pausing just short of the reassembly
+    /// timeout before each
+    fn slow_stream(packet: Packet) -> tokio::io::DuplexStream {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut writer, reader) = tokio::io::duplex(2 * MAX_PAYLOAD_BYTES);
+        tokio::spawn(async move {
+            for frame in fixtures::frames(fixtures::encode(packet)) {
+                tokio::time::sleep(REASSEMBLY_TIMEOUT - Duration::from_secs(1)).await;
+                writer.write_all(&frame).await.unwrap();
+            }
+        });
+        reader
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decode_waits_while_fragments_keep_coming() {
+        let board = fixtures::fragmented_board(40_000);
+        let mut reader = slow_stream(board.clone());
+        let cells = Packet::new().decode(&mut reader).await.unwrap();
+        assert_eq!(cells, board.cells);
+
+        let mut reader = slow_stream(board.clone());
+        let mut cells = IndexSet::new();
+        let decoded = Packet::new()
+            .decode_with(&mut reader, |cell| {
+                cells.insert(cell);
+            })
+            .await;
+        assert!(decoded.unwrap());
+        assert_eq!(cells, board.cells);
+    }
+
+    #[tokio::test]
+    async fn decode_with_streams_every_payload_type() {
+        use tokio_util::codec::Encoder;
+
+        // a bitmap of 1024x1024 takes 4 fragments, and cell 0 and the last cell are always set
+        let boards = [(1024, 1024, 200_000), (100, 37, 3000), (100, 37, 20)];
+        for (width, height, count) in boards {
+            for payload_type in [PayloadType::Sparse, PayloadType::Gaps] {
+                let mut packet = Packet::new();
+                packet.header.width = width as u16;
+                packet.header.height = height as u16;
+                packet.header.payload_type = payload_type;
+                packet.cells = (0..count)
+                    .map(|i: u32| {
+                        let index = i.wrapping_mul(2654435761) % (width * height);
+                        pack(index / height, index % height, height)
+                    })
+                    .chain([0, pack(width - 1, height - 1, height)])
+                    .collect();
+                let mut encoded = BytesMut::new();
+                codec::PacketCodec::new()
+                    .encode(packet.clone(), &mut encoded)
+                    .unwrap();
+                if count > 1000 && payload_type == PayloadType::Sparse {
+                    assert_eq!(encoded[PAYLOAD_TYPE], PayloadType::Dense as u8);
+                }
+
+                let mut cells = IndexSet::new();
+                let mut decoded = Packet::new();
+                assert!(decoded
+                    .decode_with(&mut &encoded[..], |cell| {
+                        cells.insert(cell);
+                    })
+                    .await
+                    .unwrap());
+                assert_eq!(cells.len(), packet.cells.len());
+                assert!(cells.iter().all(|cell| packet.cells.contains(cell)));
+            }
+        }
+    }
+
+    fn pack(x: u32, y: u32, height: u32) -> u32 {
+        x << coord_bits(height) | y
+    }
+
+    #[test]
+    fn values_wider_than_a_cell_decode_on_the_same_path() {
+        // coordinates of a 2^21 x 2^21 board, 42 bits each, and 100 bit values past what u64 holds
+        for bits in [42, 100] {
+            let values: Vec<u128> = (0..1000u128)
+                .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15_F39C_C060_5CED_C835) >> (128 - bits))
+                .chain([0])
+                .collect();
+            let mut data = Vec::new();
+            packing::pack(values.iter().copied(), bits, &mut data);
+
+            let packet = Packet::new();
+            assert_eq!(
+                packet.decode_values::<u128>(&data, bits, values.len()),
+                values
+            );
+            if bits <= packing::max_bits::<u64>() {
+                let narrow: Vec<u128> = packet
+                    .decode_values::<u64>(&data, bits, values.len())
+                    .into_iter()
+                    .map(u128::from)
+                    .collect();
+                assert_eq!(narrow, values);
+            }
+        }
+    }
+
+    /// a single frame carrying `payload` as the bitmap of an 8x8 board
+    fn dense_frame(payload: &[u8], cell_count: u32) -> Vec<u8> {
+        let mut header = Header::new();
+        header.payload_type = PayloadType::Dense;
+        header.width = 8;
+        header.height = 8;
+        header.length = payload.len() as u32;
+        header.cell_count = cell_count;
+        header.checksum = checksum::frame_checksum(&header.to_bytes()[..CHECKSUM], payload);
+        let mut frame = header.to_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn bitmap_of_the_wrong_length_is_rejected() {
+        // one cell in the first byte, then a byte short of the board and a byte over it
+        for length in [7, 9] {
+            let mut payload = vec![0u8; length];
+            payload[0] = 0x80;
+            let frame = dense_frame(&payload, 1);
+
+            let decoded = Packet::new().decode(&mut &frame[..]).await;
+            let streamed = Packet::new().decode_with(&mut &frame[..], |_| {}).await;
+            for result in [decoded.map(|_| ()), streamed.map(|_| ())] {
+                match result {
+                    Err(DecodeError::LengthMismatch {
+                        expected: 8,
+                        received,
+                    }) if received == length => {}
+                    other => panic!("{} byte bitmap gave {:?}", length, other),
+                }
+            }
+        }
+        let frame = dense_frame(&[0x80, 0, 0, 0, 0, 0, 0, 1], 2);
+        assert_eq!(
+            Packet::new().decode(&mut &frame[..]).await.unwrap().len(),
+            2
+        );
+    }
+
+    /// a 100x37 board, whose 13 bit coordinates don't line up with payload bytes
+    fn strict_board(cells: &[(u32, u32)]) -> (Packet, Vec<u8>) {
+        let mut packet = Packet::new();
+        packet.header.width = 100;
+        packet.header.height = 37;
+        let mut data = Vec::new();
+        packing::pack::<u64, _>(
+            cells.iter().map(|&(x, y)| pack(x, y, 37) as u64),
+            packet.coord_len(),
+            &mut data,
+        );
+        (packet, data)
+    }
+
+    #[test]
+    fn strict_decode_rejects_off_board_cells() {
+        // x past the width and y past the height, both still within their bits. the bad cell is
+        // the 4th, bits 39..52, which start in byte 4
+        for bad in [(100, 5), (99, 40)] {
+            let (packet, data) = strict_board(&[(1, 2), (3, 4), (5, 6), bad]);
+            match packet.decode_payload_strict::<IndexSet<u32>>(&data, packet.coord_len(), 4) {
+                Err(DecodeError::CoordinateOutOfRange {
+                    x,
+                    y,
+                    width: 100,
+                    height: 37,
+                    offset: at,
+                }) if (x, y) == bad && at == 4 => {}
+                other => panic!("{:?} gave {:?}", bad, other),
+            }
+            // the lenient decode keeps it
+            let cells: IndexSet<u32> = packet.decode_payload(&data, packet.coord_len(), 4);
+            assert!(cells.contains(&pack(bad.0, bad.1, 37)));
+        }
+    }
+
+    #[test]
+    fn strict_decode_rejects_duplicate_cells_where_they_repeat() {
+        // the repeat is the 6th cell, bits 65..78, which start in byte 8
+        let cells = [(1, 2), (3, 4), (5, 6), (7, 8), (9, 10), (3, 4), (11, 12)];
+        let (packet, data) = strict_board(&cells);
+        match packet.decode_payload_strict::<IndexSet<u32>>(&data, packet.coord_len(), cells.len())
+        {
+            Err(DecodeError::DuplicateCell {
+                x: 3,
+                y: 4,
+                offset: 8,
+            }) => {}
+            other => panic!("expected a duplicate at byte 8, got {:?}", other),
+        }
+        let lenient: IndexSet<u32> = packet.decode_payload(&data, packet.coord_len(), cells.len());
+        assert_eq!(lenient.len(), cells.len() - 1);
+    }
+
+    /// neighbours worked out the long way, with signed offsets wrapped round the board
+    fn expected_neighbours(x: u32, y: u32, width: u32, height: u32) -> Vec<u32> {
+        let mut expected = Vec::new();
+        for dx in -1i64..=1 {
+            for dy in -1i64..=1 {
+                if (dx, dy) != (0, 0) {
+                    let nx = (x as i64 + dx).rem_euclid(width as i64) as u32;
+                    let ny = (y as i64 + dy).rem_euclid(height as i64) as u32;
+                    expected.push(pack(nx, ny, height));
+                }
+            }
+        }
+        expected.sort_unstable();
+        expected
+    }
+
+    /// the corners, the middle of every edge and a cell inside
+    fn edge_cells(width: u32, height: u32) -> Vec<(u32, u32)> {
+        let (right, top) = (width - 1, height - 1);
+        let (mid_x, mid_y) = (width / 2, height / 2);
+        vec![
+            (0, 0),
+            (right, 0),
+            (0, top),
+            (right, top),
+            (mid_x, 0),
+            (mid_x, top),
+            (0, mid_y),
+            (right, mid_y),
+            (mid_x, mid_y),
+        ]
+    }
+
+    #[test]
+    fn neighbours_wrap_at_every_edge() {
+        // 100x37 isn't a power of two either way, so coordinates don't fill their bits
+        for (width, height) in [(16, 16), (64, 64), (512, 512), (100, 37)] {
+            for (x, y) in edge_cells(width, height) {
+                let mut neighbours =
+                    neighbour_positions(pack(x, y, height), width, height).to_vec();
+                neighbours.sort_unstable();
+                assert_eq!(
+                    neighbours,
+                    expected_neighbours(x, y, width, height),
+                    "({}, {}) on {}x{}",
+                    x,
+                    y,
+                    width,
+                    height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_match_on_every_cell() {
+        for (width, height) in [(16, 16), (100, 37)] {
+            for x in 0..width {
+                for y in 0..height {
+                    let mut neighbours =
+                        neighbour_positions(pack(x, y, height), width, height).to_vec();
+                    neighbours.sort_unstable();
+                    assert_eq!(neighbours, expected_neighbours(x, y, width, height));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corners_are_neighbours_across_the_wrap() {
+        for (width, height) in [(16, 16), (64, 64), (512, 512), (100, 37)] {
+            let (right, top) = (width - 1, height - 1);
+            let corners: IndexSet<u32> = [(0, 0), (right, 0), (0, top), (right, top)]
+                .into_iter()
+                .map(|(x, y)| pack(x, y, height))
+                .collect();
+            for index in 0..corners.len() {
+                assert_eq!(corners.neighbours(index, width, height), 3);
+            }
+            // the middle of the board touches none of them
+            assert_eq!(
+                corners.live_neighbours(pack(width / 2, height / 2, height), width, height),
+                0
+            );
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::ops::{BitOrAssign, Shl, ShlAssign, Shr};
+
+// fixed width bit packing, the encoding behind coordinate lists. values are written one after
+// another, most significant bit first, with no gaps, and the last byte is padded with zeros.
+//
+// the accumulator holds the bits between whole bytes and whole values. fewer than 8 bits are left
+// over between values when packing, and fewer than a value's width between bytes when unpacking,
+// so an accumulator can take values up to `BITS - 7` wide. the same goes for `unpack_words`, which
+// loads a whole accumulator of bytes at once and starts it fewer than 8 bits into the first.
+//
+// a u64 covers every coordinate a 16 bit width and height can make, and is what `Board` decoding
+// uses. values wider than that, from boards over 65536x65536 or with extra bits per cell, go
+// through the same functions with a u128, up to 121 bits, see `Packet::decode_values`.
+
+/// an unsigned integer the packer can buffer bits in
+pub trait Accumulator:
+    Copy + BitOrAssign + Shl<u32, Output = Self> + Shr<u32, Output = Self> + ShlAssign<u32>
+{
+    const BITS: u32;
+    const ZERO: Self;
+
+    fn from_byte(byte: u8) -> Self;
+
+    /// the `BITS / 8` bytes of `data` from `start` as a big endian value, zero filled past its end
+    fn load(data: &[u8], start: usize) -> Self;
+
+    /// the highest 8 bits
+    fn top_byte(self) -> u8;
+}
+
+macro_rules! accumulator {
+    ($($t:ty),*) => {
+        $(
+            impl Accumulator for $t {
+                const BITS: u32 = <$t>::BITS;
+                const ZERO: Self = 0;
+
+                fn from_byte(byte: u8) -> Self {
+                    byte as $t
+                }
+
+                fn load(data: &[u8], start: usize) -> Self {
+                    const BYTES: usize = std::mem::size_of::<$t>();
+                    match data.get(start..start + BYTES) {
+                        Some(bytes) => <$t>::from_be_bytes(bytes.try_into().unwrap()),
+                        None => {
+                            let mut bytes = [0u8; BYTES];
+                            let tail = data.get(start..).unwrap_or(&[]);
+                            bytes[..tail.len()].copy_from_slice(tail);
+                            <$t>::from_be_bytes(bytes)
+                        }
+                    }
+                }
+
+                fn top_byte(self) -> u8 {
+                    (self >> (Self::BITS - 8)) as u8
+                }
+            }
+        )*
+    };
+}
+
+accumulator!(u32, u64, u128);
+
+/// widest value an `A` accumulator can pack and unpack
+pub const fn max_bits<A: Accumulator>() -> u32 {
+    A::BITS - 7
+}
+
+fn check_bits<A: Accumulator>(bits: u32) {
+    assert!(
+        (1..=max_bits::<A>()).contains(&bits),
+        "values must be between 1 and {} bits for a {} bit accumulator, got {}",
+        max_bits::<A>(),
+        A::BITS,
+        bits
+    );
+}
+
+/// packed size in bytes of `count` values of `bits` bits
+pub fn packed_len(count: usize, bits: u32) -> usize {
+    (count * bits as usize).div_ceil(8)
+}
+
+/// appends `values`, `bits` wide each, to `data`. bits above the low `bits` of a value must be zero
+pub fn pack<A, I>(values: I, bits: u32, data: &mut Vec<u8>)
+where
+    A: Accumulator,
+    I: IntoIterator<Item = A>,
+{
+    check_bits::<A>(bits);
+    let mut buffer = A::ZERO;
+    let mut bit_count = 0;
+    for value in values {
+        buffer |= value << (A::BITS - bits - bit_count);
+        bit_count += bits;
+        while bit_count >= 8 {
+            data.push(buffer.top_byte());
+            buffer <<= 8;
+            bit_count -= 8;
+        }
+    }
+    // this has to go by the bit count rather than whether the buffer is empty,
+    // as the last value's bits can be zeros themselves
+    if bit_count > 0 {
+        data.push(buffer.top_byte());
+    }
+}
+
+/// incremental unpacker for `count` values of `bits` bits, fed the packed bytes in any chunks
+#[derive(Debug)]
+pub struct Unpacker<A: Accumulator> {
+    // bits not yet unpacked, left aligned so the next byte always goes straight after them
+    buffer: A,
+    bit_count: u32,
+    bits: u32,
+    remaining: u32,
+}
+
+impl<A: Accumulator> Unpacker<A> {
+    pub fn new(bits: u32, count: u32) -> Self {
+        check_bits::<A>(bits);
+        Unpacker {
+            buffer: A::ZERO,
+            bit_count: 0,
+            bits,
+            remaining: count,
+        }
+    }
+
+    /// unpacks as many values as `chunk` completes, passing each to `emit` in order.
+    /// any bits left over are kept for the next call, anything after the last value is ignored
+    pub fn feed<F: FnMut(A)>(&mut self, chunk: &[u8], mut emit: F) {
+        for &byte in chunk {
+            if self.remaining == 0 {
+                return;
+            }
+            self.buffer |= A::from_byte(byte) << (A::BITS - 8 - self.bit_count);
+            self.bit_count += 8;
+
+            while self.remaining > 0 && self.bit_count >= self.bits {
+                emit(self.buffer >> (A::BITS - self.bits));
+                self.buffer <<= self.bits;
+                self.bit_count -= self.bits;
+                self.remaining -= 1;
+            }
+        }
+    }
+
+    /// values still to come
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
+/// unpacks `count` values from a whole packed buffer, passing each to `emit`. rather than a byte
+/// at a time this loads a whole `A` of bytes and takes every value that lies wholly inside it,
+/// which with a u64 and an 18 bit board is 3 values a load
+pub fn unpack_words<A, F>(data: &[u8], bits: u32, count: usize, mut emit: F)
+where
+    A: Accumulator,
+    F: FnMut(A),
+{
+    check_bits::<A>(bits);
+    let mut position = 0;
+    let mut left = count;
+    while left > 0 {
+        let shift = (position % 8) as u32;
+        let mut word = A::load(data, position / 8) << shift;
+        let values = (((A::BITS - shift) / bits) as usize).min(left);
+        for _ in 0..values {
+            emit(word >> (A::BITS - bits));
+            word <<= bits;
+        }
+        position += values * bits as usize;
+        left -= values;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// small deterministic generator so failures reproduce
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0
+        }
+
+        /// a random value of at most `bits` bits
+        fn value(&mut self, bits: u32) -> u128 {
+            let value = (self.next() as u128) << 64 | self.next() as u128;
+            value >> (128 - bits)
+        }
+    }
+
+    /// random values of `bits` bits, with a zero value last as padding could swallow it
+    fn values(rng: &mut Lcg, bits: u32) -> Vec<u128> {
+        let count = rng.next() % 200;
+        let mut values: Vec<u128> = (0..count).map(|_| rng.value(bits)).collect();
+        values.push(0);
+        values
+    }
+
+    fn round_trip<A: Accumulator + std::fmt::Debug + PartialEq>(
+        rng: &mut Lcg,
+        bits: u32,
+        to_acc: fn(u128) -> A,
+    ) {
+        let values: Vec<A> = values(rng, bits).into_iter().map(to_acc).collect();
+        let mut data = Vec::new();
+        pack(values.iter().copied(), bits, &mut data);
+        assert_eq!(data.len(), packed_len(values.len(), bits));
+
+        // any chunking of the bytes gives back the same values
+        let mut unpacked = Vec::new();
+        let mut unpacker = Unpacker::<A>::new(bits, values.len() as u32);
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at((rng.next() as usize % 16 + 1).min(rest.len()));
+            unpacker.feed(chunk, |value| unpacked.push(value));
+            rest = tail;
+        }
+        assert_eq!(unpacker.remaining(), 0);
+        assert_eq!(unpacked, values, "{} bits", bits);
+    }
+
+    #[test]
+    fn unpacker_round_trips_every_width() {
+        let mut rng = Lcg(1);
+        for _ in 0..20 {
+            for bits in 1..=max_bits::<u32>() {
+                round_trip::<u32>(&mut rng, bits, |v| v as u32);
+            }
+            for bits in 1..=max_bits::<u64>() {
+                round_trip::<u64>(&mut rng, bits, |v| v as u64);
+            }
+            for bits in 1..=max_bits::<u128>() {
+                round_trip::<u128>(&mut rng, bits, |v| v);
+            }
+        }
+    }
+
+    fn words_round_trip<A: Accumulator + std::fmt::Debug + PartialEq>(
+        rng: &mut Lcg,
+        bits: u32,
+        to_acc: fn(u128) -> A,
+    ) {
+        let values: Vec<A> = values(rng, bits).into_iter().map(to_acc).collect();
+        let mut data = Vec::new();
+        pack(values.iter().copied(), bits, &mut data);
+
+        let mut unpacked = Vec::new();
+        unpack_words(&data, bits, values.len(), |value: A| unpacked.push(value));
+        assert_eq!(unpacked, values, "{} bits", bits);
+    }
+
+    #[test]
+    fn unpack_words_round_trips_every_width() {
+        let mut rng = Lcg(2);
+        for _ in 0..20 {
+            for bits in 1..=max_bits::<u32>() {
+                words_round_trip::<u32>(&mut rng, bits, |v| v as u32);
+            }
+            for bits in 1..=max_bits::<u64>() {
+                words_round_trip::<u64>(&mut rng, bits, |v| v as u64);
+            }
+            for bits in 1..=max_bits::<u128>() {
+                words_round_trip::<u128>(&mut rng, bits, |v| v);
+            }
+        }
+    }
+
+    #[test]
+    fn padding_is_not_a_value() {
+        // 3 values of 3 bits leave 7 bits of padding, room for 2 more zeros
+        let mut data = Vec::new();
+        pack([5u64, 0, 0], 3, &mut data);
+        assert_eq!(data, [0b1010_0000, 0]);
+
+        let mut unpacked = Vec::new();
+        unpack_words(&data, 3, 3, |value: u64| unpacked.push(value));
+        assert_eq!(unpacked, [5, 0, 0]);
+    }
+
+    #[test]
+    fn nothing_packs_to_nothing() {
+        let mut data = Vec::new();
+        pack(std::iter::empty::<u64>(), 18, &mut data);
+        assert!(data.is_empty());
+        unpack_words(&data, 18, 0, |_: u64| panic!("no values to unpack"));
+    }
+}
@@ -0,0 +1,176 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{DecodeError, Header};
+
+// a fragmented message is followed the same way whether it comes through the codec or is read
+// straight off a stream. each fragment has to carry on from the one before under the same msg_id,
+// and the message is given up on once the reassembly timeout passes without another fragment
+// arriving, however long it has been going in all. payload bytes are left to the caller, which may
+// buffer them or decode them as they come, but a message can't grow past its size limit either
+// way, so a peer can't have the receiver buffer an unbounded payload.
+//
+// time is tokio's clock, so tests can pause it and step past the timeout without waiting.
+
+/// the progress of one message arriving fragment by fragment
+#[derive(Debug)]
+pub(crate) struct Reassembly {
+    // header of the last fragment received in full, none until the first is
+    received: Option<Header>,
+    // payload bytes received so far
+    length: usize,
+    max_length: usize,
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Reassembly {
+    pub(crate) fn new(timeout: Duration, max_length: usize) -> Self {
+        Self {
+            received: None,
+            length: 0,
+            max_length,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    /// checks the frame with `header` can come next in the message, and that its payload won't
+    /// take the message past `max_length`. done as soon as its header is in, before any of its
+    /// payload is read
+    pub(crate) fn check(&self, header: &Header) -> Result<(), DecodeError> {
+        header.check_sequence(self.received.as_ref())?;
+        let length = self.length + header.length as usize;
+        if length > self.max_length {
+            return Err(DecodeError::PayloadTooLarge {
+                length,
+                max: self.max_length,
+            });
+        }
+        Ok(())
+    }
+
+    /// records the fragment with `header` as received in full, which gives the next one a whole
+    /// timeout to arrive in. returns the header for the whole message if that was its last fragment
+    pub(crate) fn add(&mut self, mut header: Header) -> Option<Header> {
+        self.length += header.length as usize;
+        if header.is_last_fragment() {
+            // from here on the header describes the whole payload rather than its last fragment
+            header.length = self.length as u32;
+            return Some(header);
+        }
+        self.deadline = Some(Instant::now() + self.timeout);
+        self.received = Some(header);
+        None
+    }
+
+    /// whether the next fragment should have arrived by now
+    pub(crate) fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// the message stops here, which is fine before its first fragment but not part way through
+    pub(crate) fn stopped(&self) -> Result<(), DecodeError> {
+        match &self.received {
+            Some(received) => Err(received.missing_fragments()),
+            None => Ok(()),
+        }
+    }
+
+    /// runs `read`, failing with `MissingFragments` if it is still going when the next fragment
+    /// should have arrived. until the first fragment is in there is nothing to wait for
+    /// and `read` can take as long as it likes
+    pub(crate) async fn by_deadline<T, F>(&self, read: F) -> Result<T, DecodeError>
+    where
+        F: Future<Output = Result<T, DecodeError>>,
+    {
+        match (self.deadline, &self.received) {
+            (Some(deadline), Some(received)) => tokio::time::timeout_at(deadline, read)
+                .await
+                .unwrap_or_else(|_| Err(received.missing_fragments())),
+            _ => read.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FunctionCall;
+
+    fn fragment(fragment: u16, fragment_count: u16, length: u32) -> Header {
+        let mut header = Header::new();
+        header.fn_call = FunctionCall::ProcessSlice;
+        header.msg_id = 3;
+        header.fragment = fragment;
+        header.fragment_count = fragment_count;
+        header.length = length;
+        header
+    }
+
+    #[test]
+    fn whole_message_header_has_the_whole_length() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(1), 100);
+        for (i, length) in [40, 40, 20].into_iter().enumerate() {
+            let header = fragment(i as u16, 3, length);
+            reassembly.check(&header).unwrap();
+            match reassembly.add(header) {
+                Some(header) => {
+                    assert_eq!(i, 2);
+                    assert_eq!(header.length, 100);
+                }
+                None => assert!(i < 2),
+            }
+        }
+    }
+
+    #[test]
+    fn message_may_not_grow_past_its_limit() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(1), 100);
+        reassembly.check(&fragment(0, 3, 60)).unwrap();
+        reassembly.add(fragment(0, 3, 60));
+        assert!(matches!(
+            reassembly.check(&fragment(1, 3, 41)),
+            Err(DecodeError::PayloadTooLarge {
+                length: 101,
+                max: 100
+            })
+        ));
+    }
+
+    #[test]
+    fn fragments_have_to_follow_on() {
+        let mut reassembly = Reassembly::new(Duration::from_secs(1), 100);
+        assert!(reassembly.check(&fragment(1, 3, 10)).is_err());
+        reassembly.add(fragment(0, 3, 10));
+        assert!(reassembly.check(&fragment(2, 3, 10)).is_err());
+        assert!(reassembly.check(&fragment(1, 4, 10)).is_err());
+        reassembly.check(&fragment(1, 3, 10)).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn each_fragment_restarts_the_timeout() {
+        let timeout = Duration::from_secs(5);
+        let mut reassembly = Reassembly::new(timeout, 100);
+        assert!(reassembly.stopped().is_ok());
+        reassembly.add(fragment(0, 3, 10));
+        tokio::time::advance(timeout - Duration::from_secs(1)).await;
+        assert!(!reassembly.is_expired());
+        reassembly.add(fragment(1, 3, 10));
+        tokio::time::advance(timeout - Duration::from_secs(1)).await;
+        assert!(!reassembly.is_expired());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(reassembly.is_expired());
+        assert!(matches!(
+            reassembly.stopped(),
+            Err(DecodeError::MissingFragments {
+                msg_id: 3,
+                received: 2,
+                expected: 3
+            })
+        ));
+    }
+}
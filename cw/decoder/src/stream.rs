@@ -0,0 +1,123 @@
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::packing::Unpacker;
+use crate::reassembly::Reassembly;
+use crate::{
+    bitmap, checksum, read_header, DecodeError, Packet, PayloadType, CHECKSUM, HEADER_SIZE_BYTES,
+    MAX_MESSAGE_BYTES, REASSEMBLY_TIMEOUT,
+};
+
+// decoding a payload a chunk at a time, so a board's cells can be handed on as the payload
+// arrives instead of after the whole payload has been read into memory.
+// for a packed coordinate list the unpacker keeps the bits of a coordinate split across two chunks
+// until the rest turns up, and stops after the header's cell count so the padding in the last byte
+// is never read as a cell. a bitmap needs nothing carried over, each byte is placed by its offset.
+
+/// payload bytes read from the stream per chunk by `Packet::decode_with`
+const CHUNK_SIZE: usize = 8 * 1024;
+
+impl Packet {
+    /// reads one message from `stream` like `decode`, but passes each cell to `on_cell` as soon
+    /// as it is decoded instead of collecting them, returning false if the peer closed the
+    /// connection before sending a header.
+    ///
+    /// coordinate lists, deltas and bitmaps are decoded in chunks as they are read, fragment after
+    /// fragment, gap payloads are read whole first. a fragment's checksum can only be
+    /// checked once its last byte is in, so on an error every cell already passed on has to be
+    /// thrown away. cells aren't held on to, so there is no strict mode here: checking them
+    /// against the board and for repeats is left to `on_cell`.
+    pub async fn decode_with<R, F>(
+        &mut self,
+        stream: &mut R,
+        mut on_cell: F,
+    ) -> Result<bool, DecodeError>
+    where
+        R: AsyncRead + Unpin,
+        F: FnMut(u32),
+    {
+        let mut header = [0u8; HEADER_SIZE_BYTES];
+        if !read_header(stream, &mut header).await? {
+            return Ok(false);
+        }
+        let mut reassembly = Reassembly::new(REASSEMBLY_TIMEOUT, MAX_MESSAGE_BYTES);
+        self.decode_frame_header(&header, &reassembly)?;
+
+        let payload_type = self.header.payload_type;
+        let (width, height) = (self.header.width as u32, self.header.height as u32);
+        let coordinate_length = self.coord_len();
+        let mut unpacker = Unpacker::<u64>::new(coordinate_length, self.header.cell_count);
+        // gaps are kept until the whole payload is in
+        let mut payload = BytesMut::new();
+        // bitmap cells passed on so far, to check against the cell count at the end
+        let mut decoded = 0;
+        let mut total = 0;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        self.header = loop {
+            let mut crc = checksum::update(checksum::INITIAL, &header[..CHECKSUM]);
+            let mut remaining = self.header.length as usize;
+            while remaining > 0 {
+                let want = remaining.min(CHUNK_SIZE);
+                let read = async { Ok(stream.read(&mut chunk[..want]).await?) };
+                let n = reassembly.by_deadline(read).await?;
+                if n == 0 {
+                    return Err(DecodeError::LengthMismatch {
+                        expected: self.header.length as usize,
+                        received: self.header.length as usize - remaining,
+                    });
+                }
+                crc = checksum::update(crc, &chunk[..n]);
+                match payload_type {
+                    PayloadType::Sparse | PayloadType::Delta => {
+                        unpacker.feed(&chunk[..n], |cell| on_cell(cell as u32))
+                    }
+                    PayloadType::Dense => {
+                        let offset = total + self.header.length as usize - remaining;
+                        bitmap::decode_chunk(&chunk[..n], offset, width, height, |cell| {
+                            decoded += 1;
+                            on_cell(cell);
+                        });
+                    }
+                    PayloadType::Gaps => payload.extend_from_slice(&chunk[..n]),
+                }
+                remaining -= n;
+            }
+            if crc != self.header.checksum {
+                return Err(DecodeError::BadChecksum {
+                    expected: self.header.checksum,
+                    computed: crc,
+                });
+            }
+            total += self.header.length as usize;
+
+            if let Some(header) = reassembly.add(self.header.clone()) {
+                break header;
+            }
+            if !reassembly
+                .by_deadline(read_header(stream, &mut header))
+                .await?
+            {
+                return reassembly.stopped().map(|()| false);
+            }
+            self.decode_frame_header(&header, &reassembly)?;
+        };
+
+        match payload_type {
+            PayloadType::Sparse | PayloadType::Delta => self.check_payload_len(total)?,
+            PayloadType::Dense => {
+                self.check_payload_len(total)?;
+                if decoded != self.header.cell_count as usize {
+                    return Err(DecodeError::CellCountMismatch {
+                        expected: self.header.cell_count,
+                        decoded,
+                    });
+                }
+            }
+            PayloadType::Gaps => self
+                .decode_cells(&payload, false)?
+                .into_iter()
+                .for_each(on_cell),
+        }
+        Ok(true)
+    }
+}
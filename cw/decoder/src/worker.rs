@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use indexmap::IndexSet;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+use tokio_util::codec::Framed;
+
+use crate::board::Board;
+use crate::codec::PacketCodec;
+use crate::handshake::handshake;
+use crate::heartbeat::Heartbeat;
+use crate::{
+    delta, neighbour_positions, Cell, DecodeError, FunctionCall, Header, Packet, PayloadType,
+};
+
+// a worker connects to the broker and then serves its requests over that one connection.
+// a slice packet holds the live cells of a band of rows plus the halo rows either side of it.
+// the worker steps every row it was given and the broker throws away the halo rows' results,
+// as without their own outer neighbours those are not correct.
+//
+// slices are stepped on the blocking pool so the connection keeps answering the broker's
+// heartbeats meanwhile, and the worker gives up on a broker that has stopped sending them.
+
+/// connects to the broker at `addr` and processes slices until told to quit.
+/// fails if nothing, heartbeats included, arrives from the broker for `heartbeat.silence_limit()`
+pub async fn run<A: ToSocketAddrs>(addr: A, heartbeat: Heartbeat) -> Result<(), DecodeError> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut framed = Framed::new(stream, PacketCodec::new());
+    let version = handshake(&mut framed).await?;
+    println!("worker connected, protocol version {}", version);
+
+    let (sink, mut stream) = framed.split();
+    let sink = Arc::new(Mutex::new(sink));
+    let silence_limit = heartbeat.silence_limit();
+    loop {
+        let mut packet = match tokio::time::timeout(silence_limit, stream.next()).await {
+            Ok(Some(packet)) => packet?,
+            Ok(None) => break,
+            Err(_) => {
+                return Err(DecodeError::Other(format!(
+                    "nothing from the broker for {:?}, assuming it is dead",
+                    silence_limit
+                )))
+            }
+        };
+        match packet.header.fn_call {
+            // echoed straight back under the same msg_id
+            FunctionCall::Heartbeat => sink.lock().await.send(packet).await?,
+            FunctionCall::ProcessSlice if packet.header.payload_type == PayloadType::Delta => {
+                // slices are sent whole, there is no earlier slice to apply this to
+                packet.header.fn_call = FunctionCall::Keyframe;
+                packet.header.payload_type = PayloadType::Sparse;
+                packet.cells.clear();
+                sink.lock().await.send(packet).await?;
+            }
+            FunctionCall::ProcessSlice => {
+                let sink = sink.clone();
+                tokio::spawn(async move {
+                    let msg_id = packet.header.msg_id;
+                    let header = packet.header.clone();
+                    let reply = match tokio::task::spawn_blocking(|| process_slice(packet)).await {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            // the broker is waiting on this msg_id, tell it rather than let it hang
+                            eprintln!("slice {} panicked: {}", msg_id, e);
+                            failed(header)
+                        }
+                    };
+                    if let Err(e) = sink.lock().await.send(reply).await {
+                        eprintln!("failed to answer message {}: {}", msg_id, e);
+                    }
+                });
+            }
+            FunctionCall::Quit => break,
+            fn_call => eprintln!("worker ignoring {:?} from broker", fn_call),
+        }
+    }
+    Ok(())
+}
+
+/// steps a slice and turns it into the reply, which goes back under the request's msg_id.
+/// a slice of a board with no rows or columns can't be stepped and is answered `Failed`
+fn process_slice(mut packet: Packet) -> Packet {
+    let width = packet.header.width as u32;
+    let height = packet.header.height as u32;
+    if width == 0 || height == 0 {
+        eprintln!(
+            "slice {} is of a {}x{} board",
+            packet.header.msg_id, width, height
+        );
+        return failed(packet.header);
+    }
+    let slice = std::mem::take(&mut packet.cells);
+    packet.cells = next_generation(&slice, width, height);
+    packet.header.turn = packet.header.turn.wrapping_add(1);
+    // the broker still holds the slice it sent, so only the flips need to go back
+    delta::delta_against(&mut packet, &slice);
+    packet
+}
+
+/// an empty `Failed` reply to the request with `header`
+fn failed(mut header: Header) -> Packet {
+    header.fn_call = FunctionCall::Failed;
+    header.payload_type = PayloadType::Sparse;
+    let mut packet = Packet::new();
+    packet.header = header;
+    packet
+}
+
+/// applies the Life rules once: a live cell with 2 or 3 live neighbours survives,
+/// a dead cell with exactly 3 is born, everything else is dead next turn
+pub fn next_generation<B: Board>(cells: &B, width: u32, height: u32) -> B {
+    // only live cells and their neighbours can be alive next turn
+    let mut candidates = IndexSet::with_capacity(cells.len() * 9);
+    for cell in cells.cells() {
+        candidates.insert(cell);
+        candidates.extend(neighbour_positions(cell, width, height));
+    }
+
+    let mut next = B::for_board(width, height, cells.len());
+    for cell in candidates {
+        let alive = match cells.live_neighbours(cell, width, height) {
+            3 => true,
+            2 => cells.contains(cell),
+            _ => false,
+        };
+        if alive {
+            next.insert(cell);
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BitBoard;
+    use crate::coord_bits;
+
+    fn board<B: Board>(cells: &[(u32, u32)], width: u32, height: u32) -> B {
+        let mut board = B::for_board(width, height, cells.len());
+        for &(x, y) in cells {
+            board.insert(x << coord_bits(height) | y);
+        }
+        board
+    }
+
+    fn sorted<B: Board>(board: &B) -> Vec<u32> {
+        let mut cells: Vec<u32> = board.cells().collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn blinker_oscillates() {
+        let (width, height) = (8, 8);
+        let vertical: IndexSet<u32> = board(&[(3, 2), (3, 3), (3, 4)], width, height);
+        let horizontal: IndexSet<u32> = board(&[(2, 3), (3, 3), (4, 3)], width, height);
+
+        let next = next_generation(&vertical, width, height);
+        assert_eq!(sorted(&next), sorted(&horizontal));
+        assert_eq!(
+            sorted(&next_generation(&next, width, height)),
+            sorted(&vertical)
+        );
+    }
+
+    #[test]
+    fn blinker_oscillates_across_the_wrap() {
+        // split over the left and right edges, then over the top and bottom
+        let (width, height) = (10, 7);
+        let across: BitBoard = board(&[(9, 3), (0, 3), (1, 3)], width, height);
+        let down: BitBoard = board(&[(0, 2), (0, 3), (0, 4)], width, height);
+        assert_eq!(
+            sorted(&next_generation(&across, width, height)),
+            sorted(&down)
+        );
+
+        let across: BitBoard = board(&[(3, 6), (4, 6), (5, 6)], width, height);
+        let down: BitBoard = board(&[(4, 5), (4, 6), (4, 0)], width, height);
+        assert_eq!(
+            sorted(&next_generation(&across, width, height)),
+            sorted(&down)
+        );
+    }
+
+    #[test]
+    fn glider_moves_diagonally_through_the_corner() {
+        let (width, height) = (8, 6);
+        let glider = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)];
+        // starting in the bottom right corner, so it wraps both ways on its way back round
+        let start = |dx: u32, dy: u32| {
+            let cells: Vec<(u32, u32)> = glider
+                .iter()
+                .map(|&(x, y)| ((x + 6 + dx) % width, (y + 4 + dy) % height))
+                .collect();
+            board::<IndexSet<u32>>(&cells, width, height)
+        };
+
+        let mut cells = start(0, 0);
+        for step in 1..=8 {
+            for _ in 0..4 {
+                cells = next_generation(&cells, width, height);
+            }
+            assert_eq!(sorted(&cells), sorted(&start(step, step)));
+        }
+    }
+
+    #[test]
+    fn slice_of_an_empty_board_fails() {
+        for (width, height) in [(0, 8), (8, 0), (0, 0)] {
+            let mut slice = Packet::new();
+            slice.header.msg_id = 7;
+            slice.header.width = width;
+            slice.header.height = height;
+            slice.cells.insert(1);
+
+            let reply = process_slice(slice);
+            assert_eq!(reply.header.fn_call, FunctionCall::Failed);
+            assert_eq!(reply.header.msg_id, 7);
+            assert!(reply.cells.is_empty());
+        }
+    }
+}